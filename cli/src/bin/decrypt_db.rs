@@ -22,21 +22,37 @@
     clippy::unnecessary_wraps
 )]
 
-use cred_man_lib::encrypted_file;
+use cred_man_lib::encrypted_file::{self, CompositeKey};
+use cred_man_lib::secret::{self, SecretString};
+use cred_man_lib::Error;
 
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
 fn main() {
-    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Err(e) = secret::disable_core_dumps() {
+        println!("warning: unable to disable core dumps: {e}");
+    }
+    let mut args = Vec::new();
+    let mut key_file = None;
+    let mut it = std::env::args().skip(1);
+    while let Some(arg) = it.next() {
+        if arg == "--key-file" {
+            key_file = it.next().map(PathBuf::from);
+        } else {
+            args.push(arg);
+        }
+    }
     if args.len() != 1 && args.len() != 2 {
-        println!("Expected arguments: path to file");
+        println!("Expected arguments: [--key-file <path>] path to file [output file]");
         std::process::exit(1);
     }
-    let f = || -> Result<(), DecryptError> {
-        let plaintext = do_decrypt(&args[0])?;
+    let f = || -> Result<(), Error> {
+        let plaintext = do_decrypt(&args[0], key_file.as_deref())?;
         if args.len() == 1 {
-            println!("{plaintext}");
+            println!("{}", plaintext.as_str());
         } else {
             let mut f = File::create(&args[1])?;
             f.write_all(plaintext.as_bytes())?;
@@ -45,37 +61,17 @@ fn main() {
     };
     if let Err(e) = f() {
         println!("{e}");
-        std::process::exit(1);
-    }
-}
-
-#[derive(Debug)]
-enum DecryptError {
-    WrongPassword,
-    IoError(std::io::Error),
-}
-
-impl From<std::io::Error> for DecryptError {
-    fn from(e: std::io::Error) -> DecryptError {
-        DecryptError::IoError(e)
-    }
-}
-
-impl std::fmt::Display for DecryptError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            &DecryptError::WrongPassword => write!(f, "Wrong password"),
-            DecryptError::IoError(e) => write!(f, "{e}"),
-        }
+        std::process::exit(e.exit_code());
     }
 }
 
-fn do_decrypt(path: &str) -> Result<String, DecryptError> {
+fn do_decrypt(path: &str, key_file: Option<&Path>) -> Result<SecretString, Error> {
     let encrypted_data = encrypted_file::parse_file(path)?;
-    let password = linenoise::input("Enter password: ").expect("stdio should be successful");
-    let maybe_plaintext = encrypted_file::decrypt(&encrypted_data, &password);
-    match maybe_plaintext {
-        Some(plaintext) => Ok(plaintext),
-        None => Err(DecryptError::WrongPassword),
+    if encrypted_data.requires_key_file() && key_file.is_none() {
+        return Err(Error::KeyFileRequired);
     }
+    let password =
+        Zeroizing::new(linenoise::input("Enter password: ").expect("stdio should be successful"));
+    let key = CompositeKey::new(&password, key_file)?;
+    encrypted_file::decrypt(&encrypted_data, &key)
 }
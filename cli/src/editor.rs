@@ -0,0 +1,134 @@
+//! Entering multi-line values (SSH keys, recovery codes, notes) in
+//! `$VISUAL` / `$EDITOR`.
+//!
+//! The value is passed through a file that only the user can read, on a
+//! memory-backed file system so that it never reaches the disk. The file is
+//! overwritten and deleted as soon as the editor exits. Editors that keep
+//! swap or backup files put them next to it, so on the same file system.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+/// Typed instead of a value to enter it in the editor.
+pub(crate) const EDITOR_MARKER: &str = "<<";
+
+/// Lets the user edit `initial` in the editor; returns the result without
+/// the trailing newline that editors add.
+pub(crate) fn edit(initial: &str) -> io::Result<Zeroizing<String>> {
+    let file = TempFile::create(&private_tmpfs_dir()?)?;
+    file.write(initial)?;
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // Through the shell, so that $EDITOR may have arguments.
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(&file.path)
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{editor} failed: {status}")));
+    }
+    let mut value = file.read()?;
+    if value.ends_with('\n') {
+        value.pop();
+    }
+    Ok(value)
+}
+
+/// `$XDG_RUNTIME_DIR` (private to the user) or `/dev/shm`, whichever is a
+/// tmpfs.
+fn private_tmpfs_dir() -> io::Result<PathBuf> {
+    let candidates = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain([PathBuf::from("/dev/shm")]);
+    for dir in candidates {
+        if is_tmpfs(&dir) {
+            return Ok(dir);
+        }
+    }
+    Err(io::Error::other(
+        "no tmpfs for the temporary file (tried $XDG_RUNTIME_DIR and /dev/shm)",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn is_tmpfs(dir: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: all-zero bytes are a valid `statfs`.
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid `statfs` to fill.
+    let ret = unsafe { libc::statfs(path.as_ptr(), &raw mut stat) };
+    ret == 0 && stat.f_type == libc::TMPFS_MAGIC
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_tmpfs(_dir: &Path) -> bool {
+    false
+}
+
+/// Shredded and deleted when dropped.
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create(dir: &Path) -> io::Result<TempFile> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let path = dir.join(format!("cred-man-{}-{nanos}.txt", std::process::id()));
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        Ok(TempFile { path })
+    }
+
+    fn write(&self, contents: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    }
+
+    fn read(&self) -> io::Result<Zeroizing<String>> {
+        let mut contents = Zeroizing::new(String::new());
+        File::open(&self.path)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    fn shred(&self) -> io::Result<()> {
+        // Editors usually replace the file rather than write into it, so the
+        // previous contents may already be gone; this covers the current ones.
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        let len = usize::try_from(file.metadata()?.len()).unwrap_or(usize::MAX);
+        file.write_all(&vec![0; len])?;
+        file.sync_all()?;
+        fs::remove_file(&self.path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.shred() {
+            println!(
+                "warning: unable to shred {}: {e}",
+                self.path.to_string_lossy()
+            );
+        }
+    }
+}
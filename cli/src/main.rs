@@ -7,6 +7,22 @@
 //!
 //! Data is stored in ~/.local/share/credentials-manager.
 //!
+//! `--kdf scrypt|argon2id` selects the key derivation function used when
+//! the database is written (new databases use argon2id by default).
+//!
+//! `--key-file <path>` makes the key file a second unlock factor: it is
+//! required to open a database that was created with it.
+//!
+//! Typing `<<` instead of a value (in `add` and in `edit ... add|update`)
+//! opens it in `$EDITOR`, for values that span several lines.
+//!
+//! Only one process at a time can have a database open for writing; if
+//! another one holds it, the database is opened read-only.
+//!
+//! The exit status tells why opening the database failed: 2 - wrong
+//! password, 3 - key file required, 4 - damaged file, 5 - unsupported
+//! format, 6 - database is locked, 1 - any other error.
+//!
 #![warn(
     clippy::cargo,
     clippy::pedantic,
@@ -31,16 +47,25 @@
     clippy::unnecessary_wraps
 )]
 
-use chrono::naive::NaiveDateTime;
-use chrono::Local;
-use cred_man_lib::{Db, DbLoadResult, DbLocation, DbRecord};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Local, Utc};
+use cred_man_lib::attachment::{self, Attachment};
+use cred_man_lib::audit;
+use cred_man_lib::backup::{Backup, RetentionPolicy};
+use cred_man_lib::breach::{self, HashList};
+use cred_man_lib::generator::{self, PassphrasePolicy};
+use cred_man_lib::strength::{self, Strength};
+use cred_man_lib::{
+    otp, secret, CompositeKey, Db, DbLocation, DbRecord, Error, FieldKind, FieldMeta, KdfAlgorithm,
+    PasswordPolicy,
+};
 use std::cmp;
 use std::collections::BTreeMap;
-use std::io;
 use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::str::FromStr;
+use zeroize::Zeroizing;
+
+mod editor;
 
 fn parse_cmd_line(cmd_line: &str) -> (&str, &str) {
     let idx = cmd_line.find(' ').unwrap_or(cmd_line.len());
@@ -52,16 +77,13 @@ fn parse_cmd_line(cmd_line: &str) -> (&str, &str) {
     (cmd, rest)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct DbRecordDTO {
-    key: String,
-    timestamp: String,
-    value: BTreeMap<String, String>,
+fn format_time(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
 }
 
-const DTO_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
-
-type CommandHandler = fn(&mut Db, &str, &str) -> std::io::Result<bool>;
+type CommandHandler = fn(&mut Db, &str, &str) -> Result<bool, Error>;
 
 fn get_command_handler(cmd: &str) -> Option<CommandHandler> {
     match cmd {
@@ -76,27 +98,58 @@ fn get_command_handler(cmd: &str) -> Option<CommandHandler> {
         "import" => Some(import_cmd),
         "rename" => Some(rename_cmd),
         "edit" => Some(edit_cmd),
+        "passwd" => Some(passwd_cmd),
+        "backups" => Some(backups_cmd),
+        "history" => Some(history_cmd),
+        "tag" => Some(tag_cmd),
+        "untag" => Some(untag_cmd),
+        "folder" => Some(folder_cmd),
+        "field" => Some(field_cmd),
+        "attach" => Some(attach_cmd),
+        "extract" => Some(extract_cmd),
+        "detach" => Some(detach_cmd),
+        "otp" => Some(otp_cmd),
+        "gen" => Some(gen_cmd),
+        "policy" => Some(policy_cmd),
+        "audit" => Some(audit_cmd),
+        "breach" => Some(breach_cmd),
         _ => None,
     }
 }
 
-fn help_cmd(_: &mut Db, _: &str, _: &str) -> std::io::Result<bool> {
+fn help_cmd(_: &mut Db, _: &str, _: &str) -> Result<bool, Error> {
     println!("Commands:");
     println!(" help");
     println!(" quit");
     println!(" add");
-    println!(" get");
-    println!(" list");
+    println!(" get [--reveal] <key>");
+    println!(" list [--tag <tag>] [recent [created|modified|accessed] [count]]");
     println!(" find");
     println!(" del");
     println!(" dump");
     println!(" import");
     println!(" rename");
     println!(" edit");
+    println!(" passwd");
+    println!(" backups [list|verify <n>|restore <n>|prune [<last> <daily> <weekly>|off]]");
+    println!(" history <key> [--reveal|restore <n>]");
+    println!(" tag <key> <tag>...");
+    println!(" untag <key> <tag>...");
+    println!(" folder <key> [<path>]");
+    println!(" field <key> <field> [<kind>] [secret|plain]");
+    println!(" attach <key> <file> [<name>]");
+    println!(" extract <key> <name> <out>");
+    println!(" detach <key> <name>");
+    println!(" otp <key>");
+    println!(" gen [--for <key>] [<length>] [lower] [upper] [digits] [symbols] [unambiguous]");
+    println!(" gen --words [<count>] [short|long] [lower|title|upper] [sep=<separator>]");
+    println!(" policy <key> [<length>] [lower] [upper] [digits] [symbols] [unambiguous]");
+    println!(" audit [--json] [<max age in days>]");
+    println!(" breach <range directory|sorted file|http://mirror/range>");
     Ok(true)
 }
 
-fn quit_cmd(_: &mut Db, _: &str, _: &str) -> std::io::Result<bool> {
+fn quit_cmd(_: &mut Db, _: &str, _: &str) -> Result<bool, Error> {
     Ok(false)
 }
 
@@ -106,7 +159,7 @@ fn add_linenoise_history(line: &str) {
     }
 }
 
-fn del_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
+fn del_cmd(db: &mut Db, _: &str, rest_line: &str) -> Result<bool, Error> {
     let arg = match rest_line {
         x if !x.is_empty() => Some(x.to_string()),
         _ => linenoise::input("Key: "),
@@ -143,14 +196,14 @@ fn get_kv() -> KvResult {
                 let parts: Vec<_> = key.splitn(2, ' ').map(|s| s.trim().to_string()).collect();
                 let real_key = parts[0].clone();
                 if parts.len() == 2 {
-                    KvResult::Some {
-                        key: real_key,
-                        val: parts[1].clone(),
-                    }
+                    value_or_editor(real_key, parts[1].clone())
                 } else {
-                    match linenoise::input(&format!("    value for {key:}: ")) {
+                    match linenoise::input(&format!(
+                        "    value for {key:} ({} for $EDITOR, {GENERATE_MARKER} to generate): ",
+                        editor::EDITOR_MARKER
+                    )) {
                         Some(ref x) if x.is_empty() => KvResult::None,
-                        Some(x) => KvResult::Some { key, val: x },
+                        Some(x) => value_or_editor(key, x),
                         None => KvResult::None,
                     }
                 }
@@ -159,18 +212,70 @@ fn get_kv() -> KvResult {
     }
 }
 
-fn add_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
+/// Typed instead of a value to generate a password, see [`generator`].
+const GENERATE_MARKER: &str = "??";
+
+fn value_or_editor(key: String, val: String) -> KvResult {
+    if val == GENERATE_MARKER {
+        return match generator::generate(&PasswordPolicy::default()) {
+            Ok(val) => {
+                println!("    generated a password of {} characters", val.len());
+                KvResult::Some {
+                    key,
+                    val: val.to_string(),
+                }
+            }
+            Err(e) => {
+                println!("error: {e}");
+                KvResult::None
+            }
+        };
+    }
+    if val != editor::EDITOR_MARKER {
+        return KvResult::Some { key, val };
+    }
+    match editor::edit("") {
+        Ok(val) if val.is_empty() => KvResult::None,
+        Ok(val) => KvResult::Some {
+            key,
+            val: val.to_string(),
+        },
+        Err(e) => {
+            println!("error: {e}");
+            KvResult::None
+        }
+    }
+}
+
+/// Prints a value that may span several lines, indenting the lines after
+/// the first one.
+fn print_field(indent: &str, name: &str, value: &str) {
+    let mut lines = value.lines();
+    println!("{indent}{name}: {}", lines.next().unwrap_or_default());
+    for line in lines {
+        println!("{indent}  {line}");
+    }
+}
+
+/// Prints how hard a password is to guess, and how to improve it.
+fn print_strength(strength: &Strength) {
+    match strength.warning {
+        Some(warning) => println!("    strength: {strength}: {warning}"),
+        None => println!("    strength: {strength}"),
+    }
+    for suggestion in &strength.suggestions {
+        println!("      {suggestion}");
+    }
+}
+
+fn add_cmd(db: &mut Db, _: &str, rest_line: &str) -> Result<bool, Error> {
     let arg = match rest_line {
         x if !x.is_empty() => Some(x.to_string()),
         _ => linenoise::input("new key: "),
     };
     if let Some(key) = arg {
         if !key.is_empty() {
-            let mut rec = DbRecord {
-                key: key.clone(),
-                timestamp: Local::now().naive_local(),
-                value: BTreeMap::new(),
-            };
+            let mut rec = DbRecord::new(key.clone());
             loop {
                 match get_kv() {
                     KvResult::Done => {
@@ -178,6 +283,9 @@ fn add_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
                     }
                     KvResult::None => {}
                     KvResult::Some { key, val } => {
+                        if rec.field_meta(&key).kind == FieldKind::Password {
+                            print_strength(&strength::estimate_in(&rec, &key, &val));
+                        }
                         rec.value.insert(key.clone(), val);
                     }
                 }
@@ -242,7 +350,7 @@ impl RenameCmdArgs {
     }
 }
 
-fn rename_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
+fn rename_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
     match RenameCmdArgs::parse(args_line) {
         None => {
             println!("Unexpected input; expected: rename [oldname [newname]]");
@@ -258,7 +366,7 @@ fn rename_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
                     }
                     Some(mut v) => {
                         v.key.clone_from(&to);
-                        v.timestamp = Local::now().naive_local();
+                        v.modified = Utc::now();
                         db.data.insert(to.clone(), v);
                         db.save()?;
                         println!("Renamed {from} to {to}");
@@ -282,6 +390,15 @@ enum EditCmdOperation {
     Add(String, String),
     Update(String, String),
     Rename(String, String),
+    Move(String, MoveTarget),
+}
+
+#[derive(Debug)]
+enum MoveTarget {
+    Up,
+    Down,
+    /// 1-based
+    Position(usize),
 }
 
 fn ask_user(prompt: &str, history: bool) -> String {
@@ -294,6 +411,7 @@ fn ask_user(prompt: &str, history: bool) -> String {
 
 impl EditCmd {
     fn parse(args_line: &str) -> Option<Self> {
+        #![allow(clippy::too_many_lines)]
         let args = args_line
             .split_whitespace()
             .map(ToString::to_string)
@@ -306,7 +424,10 @@ impl EditCmd {
             return None;
         }
         let cmd = it.next().unwrap_or_else(|| {
-            ask_user("Enter the edit command (del, add, update, rename): ", true)
+            ask_user(
+                "Enter the edit command (del, add, update, rename, move): ",
+                true,
+            )
         });
         match cmd.as_ref() {
             "del" => {
@@ -375,19 +496,70 @@ impl EditCmd {
                     op: EditCmdOperation::Rename(subkey, new_subkey),
                 })
             }
+            "move" => {
+                let subkey = it
+                    .next()
+                    .unwrap_or_else(|| ask_user("Enter the subkey: ", true));
+                if subkey.is_empty() {
+                    return None;
+                }
+                let target = it.next().unwrap_or_else(|| {
+                    ask_user("Enter where to move it (up, down, position): ", true)
+                });
+                let target = match target.as_ref() {
+                    "up" => MoveTarget::Up,
+                    "down" => MoveTarget::Down,
+                    position => {
+                        MoveTarget::Position(usize::from_str(position).ok().filter(|p| *p > 0)?)
+                    }
+                };
+                Some(EditCmd {
+                    key,
+                    op: EditCmdOperation::Move(subkey, target),
+                })
+            }
             _ => None,
         }
     }
 }
 
-fn edit_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
+/// Lets the user enter the value of `add` and `update` in the editor, if it
+/// is given as [`editor::EDITOR_MARKER`], or generates it following the
+/// record's password policy, if it is given as [`GENERATE_MARKER`].
+fn run_editor_for(db: &Db, cmd: &mut EditCmd) -> Result<(), Error> {
+    if let EditCmdOperation::Add(subkey, value) | EditCmdOperation::Update(subkey, value) =
+        &mut cmd.op
+    {
+        if value == GENERATE_MARKER {
+            let policy = db
+                .data
+                .get(&cmd.key)
+                .and_then(|entry| entry.password_policy)
+                .unwrap_or_default();
+            *value = generator::generate(&policy)?.to_string();
+        } else if value == editor::EDITOR_MARKER {
+            let current = db
+                .data
+                .get(&cmd.key)
+                .and_then(|entry| entry.value.get(subkey.as_str()))
+                .map_or("", String::as_str);
+            *value = editor::edit(current)?.to_string();
+        }
+    }
+    Ok(())
+}
+
+fn edit_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
     let cmd = EditCmd::parse(args_line);
-    println!("edit cmd: {cmd:?}");
     match cmd {
         None => {
             println!("Unexepected input. Expected: edit [key [op_type [subkey [arg]]]]");
         }
-        Some(cmd) => {
+        Some(mut cmd) => {
+            if let Err(e) = run_editor_for(db, &mut cmd) {
+                println!("error: {e}");
+                return Ok(true);
+            }
             let should_save: bool;
             let msg: String;
             match db.data.get_mut(&cmd.key) {
@@ -397,7 +569,8 @@ fn edit_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
                 }
                 Some(entry) => match cmd.op {
                     EditCmdOperation::Del(subkey) => {
-                        if entry.value.remove(&subkey).is_some() {
+                        if entry.value.contains_key(&subkey) {
+                            entry.remove_field(&subkey);
                             should_save = true;
                             msg = format!("Subkey {subkey} removed");
                         } else {
@@ -407,6 +580,10 @@ fn edit_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
                     }
                     EditCmdOperation::Add(subkey, value) => {
                         if !entry.value.contains_key(&subkey) {
+                            entry.new_revision();
+                            if entry.field_meta(&subkey).kind == FieldKind::Password {
+                                print_strength(&strength::estimate_in(entry, &subkey, &value));
+                            }
                             entry.value.insert(subkey.clone(), value);
                             should_save = true;
                             msg = format!("Added subkey {} for {}", subkey, cmd.key);
@@ -415,29 +592,44 @@ fn edit_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
                             msg = format!("Subkey {subkey} already exists");
                         }
                     }
-                    EditCmdOperation::Update(subkey, value) => match entry.value.get_mut(&subkey) {
-                        None => {
+                    EditCmdOperation::Update(subkey, value) => {
+                        if entry.value.contains_key(&subkey) {
+                            entry.new_revision();
+                            if entry.field_meta(&subkey).kind == FieldKind::Password {
+                                print_strength(&strength::estimate_in(entry, &subkey, &value));
+                            }
+                            entry.value.insert(subkey.clone(), value);
+                            should_save = true;
+                            msg = format!("Updated subkey {} for {}", subkey, cmd.key);
+                        } else {
                             should_save = false;
                             msg = format!("Subkey {subkey} does not exist");
                         }
-                        Some(subvalue) => {
-                            *subvalue = value;
+                    }
+                    EditCmdOperation::Move(subkey, target) => {
+                        if let Some(idx) = entry.value.get_index_of(&subkey) {
+                            let to = match target {
+                                MoveTarget::Up => idx.saturating_sub(1),
+                                MoveTarget::Down => idx + 1,
+                                MoveTarget::Position(position) => position - 1,
+                            };
+                            entry.move_field(&subkey, to);
                             should_save = true;
-                            msg = format!("Updated subkey {} for {}", subkey, cmd.key);
+                            let order = entry.value.keys().map(String::as_str).collect::<Vec<_>>();
+                            msg = format!("Subkeys of {}: {}", cmd.key, order.join(", "));
+                        } else {
+                            should_save = false;
+                            msg = format!("Subkey {subkey} does not exist");
                         }
-                    },
+                    }
                     EditCmdOperation::Rename(subkey, newsubkey) => {
-                        let cur = entry.value.remove(&subkey);
-                        match cur {
-                            None => {
-                                should_save = false;
-                                msg = format!("Subkey {subkey} does not exist");
-                            }
-                            Some(cur) => {
-                                entry.value.insert(newsubkey.clone(), cur);
-                                should_save = true;
-                                msg = format!("Renamed subkey {subkey} to {newsubkey}");
-                            }
+                        if entry.value.contains_key(&subkey) {
+                            entry.rename_field(&subkey, &newsubkey);
+                            should_save = true;
+                            msg = format!("Renamed subkey {subkey} to {newsubkey}");
+                        } else {
+                            should_save = false;
+                            msg = format!("Subkey {subkey} does not exist");
                         }
                     }
                 },
@@ -451,20 +643,39 @@ fn edit_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
     Ok(true)
 }
 
-fn dump_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
+fn passwd_cmd(db: &mut Db, _: &str, _: &str) -> Result<bool, Error> {
+    let Some(current) = linenoise::input("Current password: ").map(Zeroizing::new) else {
+        return Ok(true);
+    };
+    if !db.verify_password(&current) {
+        println!("Wrong password");
+        return Ok(true);
+    }
+    let Some(new_password) = linenoise::input("New password: ").map(Zeroizing::new) else {
+        return Ok(true);
+    };
+    if new_password.is_empty() {
+        println!("Password must not be empty");
+        return Ok(true);
+    }
+    let Some(repeated) = linenoise::input("Repeat new password: ").map(Zeroizing::new) else {
+        return Ok(true);
+    };
+    if new_password != repeated {
+        println!("Passwords do not match, not changing");
+        return Ok(true);
+    }
+    db.change_password(&new_password)?;
+    println!("Password changed; older backups stay encrypted with the previous password");
+    Ok(true)
+}
+
+fn dump_cmd(db: &mut Db, _: &str, rest_line: &str) -> Result<bool, Error> {
     let mut out: Box<dyn Write> = match rest_line {
         x if !x.is_empty() => Box::new(std::fs::File::create(x)?),
         _ => Box::new(std::io::stdout()),
     };
-    let mut dto = Vec::new();
-    for r in db.data.values() {
-        dto.push(DbRecordDTO {
-            key: r.key.clone(),
-            timestamp: format!("{}", r.timestamp.format(DTO_TIME_FORMAT)),
-            value: r.value.clone(),
-        });
-    }
-    let contents = serde_json::to_string_pretty(&dto).expect("DbRecordDTO is json-serializable");
+    let contents = cred_man_lib::records_to_json(db.data.values());
     out.write_all(contents.as_bytes())?;
     out.write_all(b"\n")?;
     out.flush()?;
@@ -472,39 +683,39 @@ fn dump_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
     Ok(true)
 }
 
-fn import_from(db: &mut Db, file_name: &str) -> io::Result<()> {
+fn import_from(db: &mut Db, file_name: &str) -> Result<(), Error> {
     let mut contents = String::new();
     let mut f = std::fs::File::open(file_name)?;
     f.read_to_string(&mut contents)?;
-    let dto: Vec<DbRecordDTO> = serde_json::from_str(&contents).map_err(|e| {
-        std::io::Error::new(std::io::ErrorKind::Other, format!("Json parse error: {e}"))
-    })?;
-    for r in dto {
-        let v = DbRecord {
-            key: r.key,
-            timestamp: NaiveDateTime::parse_from_str(&r.timestamp, DTO_TIME_FORMAT).map_err(
-                |e| {
-                    std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!(
-                            "Json parse error: invalid timestamp \"{}\": {e}",
-                            r.timestamp
-                        ),
-                    )
-                },
-            )?,
-            value: r.value,
-        };
-        let k = v.key.clone();
-        db.data.insert(k, v);
-    }
+    // Everything is decoded and checked before the first record replaces
+    // one in the database.
+    let records: BTreeMap<String, DbRecord> = cred_man_lib::records_from_json(&contents)
+        .map_err(|e| match e {
+            Error::Corrupt(msg) => std::io::Error::other(format!("Json parse error: {msg}")).into(),
+            e => e,
+        })?
+        .into_iter()
+        .map(|record| (record.key.clone(), record))
+        .collect();
+    let attachments_size =
+        |record: &DbRecord| -> usize { record.attachments.values().map(Attachment::len).sum() };
+    let replaced: usize = records
+        .keys()
+        .filter_map(|key| db.data.get(key))
+        .map(attachments_size)
+        .sum();
+    attachment::check_size(
+        db.attachments_size() - replaced + records.values().map(attachments_size).sum::<usize>(),
+        attachment::MAX_TOTAL_ATTACHMENTS_SIZE,
+    )?;
+    db.data.extend(records);
 
     db.save()?;
 
     Ok(())
 }
 
-fn import_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
+fn import_cmd(db: &mut Db, _: &str, rest_line: &str) -> Result<bool, Error> {
     let filename = match rest_line {
         x if !x.is_empty() => x.trim().to_string(),
         _ => {
@@ -519,7 +730,12 @@ fn import_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
     Ok(true)
 }
 
-fn get_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
+/// Secret fields are masked unless `--reveal` is given.
+fn get_cmd(db: &mut Db, _: &str, rest_line: &str) -> Result<bool, Error> {
+    let (reveal, rest_line) = match rest_line.strip_prefix("--reveal") {
+        Some(rest) => (true, rest.trim()),
+        None => (false, rest_line),
+    };
     let arg = match rest_line {
         x if !x.is_empty() => Some(x.to_string()),
         _ => linenoise::input("find key: "),
@@ -527,15 +743,34 @@ fn get_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
     if let Some(key) = arg {
         add_linenoise_history(&key);
         if !key.is_empty() {
-            match db.data.get(&key) {
+            let last_accessed = db.data.get(&key).and_then(|val| val.accessed);
+            match db.access(&key) {
                 None => {
                     println!("there is no match for {:}", &key);
                 }
                 Some(val) => {
-                    println!("Timestamp: {}", val.timestamp.format("%Y-%m-%d %H:%M:%S"));
+                    println!("Created: {}", format_time(val.created));
+                    println!("Modified: {}", format_time(val.modified));
+                    println!(
+                        "Last accessed: {}",
+                        last_accessed.map_or_else(|| "never".to_string(), format_time)
+                    );
+                    if let Some(folder) = &val.folder {
+                        println!("Folder: {folder}");
+                    }
+                    if let Some(policy) = &val.password_policy {
+                        println!("Password policy: {policy}");
+                    }
+                    if !val.tags.is_empty() {
+                        let tags = val.tags.iter().map(String::as_str).collect::<Vec<_>>();
+                        println!("Tags: {}", tags.join(", "));
+                    }
+                    for (name, attachment) in &val.attachments {
+                        println!("Attachment: {name} ({} bytes)", attachment.len());
+                    }
                     println!("Data:");
-                    for z in &val.value {
-                        println!("{:}: {:}", z.0, z.1);
+                    for (name, value) in &val.value {
+                        print_field("", name, masked(val, name, value, reveal));
                     }
                 }
             }
@@ -544,7 +779,64 @@ fn get_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
     Ok(true)
 }
 
-fn find_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
+/// Revisions are numbered from the most recent one, starting with 1.
+fn history_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let args = args_line.split_whitespace().collect::<Vec<_>>();
+    let (key, restore, reveal) = match args.as_slice() {
+        [key] => (*key, None, false),
+        [key, "--reveal"] => (*key, None, true),
+        [key, "restore", n] => match usize::from_str(n) {
+            Ok(n) => (*key, Some(n), false),
+            Err(_) => (*key, Some(0), false),
+        },
+        _ => {
+            println!(
+                "Unrecognized arguments for history; expected: history <key> [--reveal|restore <n>]"
+            );
+            return Ok(true);
+        }
+    };
+    let Some(record) = db.data.get_mut(key) else {
+        println!("There is no key '{key:}'");
+        return Ok(true);
+    };
+    match restore {
+        None => {
+            if record.history.is_empty() {
+                println!("No previous revisions of '{key:}'");
+            }
+            for (n, revision) in record.history.iter().rev().enumerate() {
+                println!("Revision {} ({}):", n + 1, format_time(revision.timestamp));
+                for (name, value) in &revision.value {
+                    print_field("  ", name, masked(record, name, value, reveal));
+                }
+            }
+        }
+        Some(n) => {
+            let restored = n
+                .checked_sub(1)
+                .and_then(|n| record.history.len().checked_sub(n + 1))
+                .is_some_and(|idx| record.restore_revision(idx));
+            if restored {
+                db.save()?;
+                println!("Restored revision {n} of '{key:}'");
+            } else {
+                println!("There is no revision {n} of '{key:}'; see `history {key}'");
+            }
+        }
+    }
+    Ok(true)
+}
+
+fn masked<'a>(record: &DbRecord, name: &str, value: &'a str, reveal: bool) -> &'a str {
+    if reveal || !record.is_sensitive(name) {
+        value
+    } else {
+        "***"
+    }
+}
+
+fn find_cmd(db: &mut Db, _: &str, rest_line: &str) -> Result<bool, Error> {
     let arg = match rest_line {
         x if !x.is_empty() => Some(x.to_string()),
         _ => linenoise::input("find key: "),
@@ -562,39 +854,100 @@ fn find_cmd(db: &mut Db, _: &str, rest_line: &str) -> std::io::Result<bool> {
     Ok(true)
 }
 
+#[derive(Clone, Copy)]
+enum RecordTime {
+    Created,
+    Modified,
+    Accessed,
+}
+
+impl RecordTime {
+    fn parse(arg: &str) -> Option<Self> {
+        match arg {
+            "created" => Some(RecordTime::Created),
+            "modified" => Some(RecordTime::Modified),
+            "accessed" => Some(RecordTime::Accessed),
+            _ => None,
+        }
+    }
+
+    fn of(self, record: &DbRecord) -> Option<DateTime<Utc>> {
+        match self {
+            RecordTime::Created => Some(record.created),
+            RecordTime::Modified => Some(record.modified),
+            RecordTime::Accessed => record.accessed,
+        }
+    }
+}
+
 enum ListCmd {
     AllKeys,
-    Recent(Option<usize>),
+    Recent(RecordTime, Option<usize>),
 }
 
-impl ListCmd {
+struct ListCmdArgs {
+    tag: Option<String>,
+    cmd: ListCmd,
+}
+
+impl ListCmdArgs {
     fn parse(args_line: &str) -> Option<Self> {
-        let args = args_line.split_whitespace().collect::<Vec<_>>();
-        if args.is_empty() {
-            Some(ListCmd::AllKeys)
-        } else if args.len() == 1 && args[0] == "recent" {
-            Some(ListCmd::Recent(None))
-        } else if args.len() == 2 && args[0] == "recent" {
-            usize::from_str(args[1])
+        let mut args = args_line.split_whitespace().collect::<Vec<_>>();
+        let tag = match args.iter().position(|arg| *arg == "--tag") {
+            Some(idx) => {
+                let tag = args.get(idx + 1)?.to_string();
+                args.drain(idx..=idx + 1);
+                Some(tag)
+            }
+            None => None,
+        };
+        let cmd = ListCmd::parse(&args)?;
+        Some(ListCmdArgs { tag, cmd })
+    }
+}
+
+impl ListCmd {
+    fn parse(args: &[&str]) -> Option<Self> {
+        let Some((&"recent", rest)) = args.split_first() else {
+            return args.is_empty().then_some(ListCmd::AllKeys);
+        };
+        let (by, rest) = match rest.split_first() {
+            Some((arg, rest)) if RecordTime::parse(arg).is_some() => {
+                (RecordTime::parse(arg)?, rest)
+            }
+            _ => (RecordTime::Modified, rest),
+        };
+        match rest {
+            [] => Some(ListCmd::Recent(by, None)),
+            [count] => usize::from_str(count)
                 .ok()
-                .map(|c| ListCmd::Recent(Some(c)))
-        } else {
-            None
+                .map(|c| ListCmd::Recent(by, Some(c))),
+            _ => None,
         }
     }
 }
 
-fn list_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
-    let cmd = ListCmd::parse(args_line);
+fn list_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let Some(ListCmdArgs { tag, cmd }) = ListCmdArgs::parse(args_line) else {
+        println!(
+            "Unrecognized arguments for list; expected: list [--tag <tag>] [recent [created|modified|accessed] [count]]"
+        );
+        return Ok(true);
+    };
+    let records = db
+        .data
+        .values()
+        .filter(|v| tag.as_ref().is_none_or(|tag| v.tags.contains(tag)));
     match cmd {
-        Some(ListCmd::AllKeys) => {
-            for v in db.data.values() {
-                println!("{} ({})", v.key, v.timestamp.format("%Y-%m-%d %H:%M:%S"));
+        ListCmd::AllKeys => {
+            for v in records {
+                println!("{} ({})", v.key, format_time(v.modified));
             }
         }
-        Some(ListCmd::Recent(opt_count)) => {
-            let mut entries = db.data.values().collect::<Vec<_>>();
-            entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        ListCmd::Recent(by, opt_count) => {
+            // Never accessed records go last.
+            let mut entries = records.collect::<Vec<_>>();
+            entries.sort_by_key(|e| std::cmp::Reverse(by.of(e)));
             let count = opt_count.unwrap_or(10);
             while entries.len() > count {
                 let remove_idx = entries.len() - 1;
@@ -602,17 +955,548 @@ fn list_cmd(db: &mut Db, _: &str, args_line: &str) -> std::io::Result<bool> {
             }
             println!("{count} recent keys:");
             for v in entries {
-                println!("{} ({})", v.key, v.timestamp.format("%Y-%m-%d %H:%M:%S"));
+                let time = by.of(v).map_or_else(|| "never".to_string(), format_time);
+                println!("{} ({time})", v.key);
             }
         }
-        None => {
-            println!("Unrecognized arguments for list; expected: list [recent [count]]");
+    }
+    Ok(true)
+}
+
+fn tag_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    change_tags(db, args_line, "tag", DbRecord::add_tag)
+}
+
+fn untag_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    change_tags(db, args_line, "untag", DbRecord::remove_tag)
+}
+
+fn change_tags(
+    db: &mut Db,
+    args_line: &str,
+    cmd: &str,
+    change: fn(&mut DbRecord, &str) -> bool,
+) -> Result<bool, Error> {
+    let args = args_line.split_whitespace().collect::<Vec<_>>();
+    let Some((key, tags)) = args.split_first().filter(|(_, tags)| !tags.is_empty()) else {
+        println!("Unrecognized arguments for {cmd}; expected: {cmd} <key> <tag>...");
+        return Ok(true);
+    };
+    let Some(record) = db.data.get_mut(*key) else {
+        println!("There is no key '{key:}'");
+        return Ok(true);
+    };
+    let mut changed = false;
+    for tag in tags {
+        changed |= change(record, tag);
+    }
+    if changed {
+        db.save()?;
+    }
+    let record = &db.data[*key];
+    let tags = record.tags.iter().map(String::as_str).collect::<Vec<_>>();
+    println!("Tags of '{key:}': {}", tags.join(", "));
+    Ok(true)
+}
+
+/// Without a kind or secret/plain, shows the metadata of the field.
+fn field_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let args = args_line.split_whitespace().collect::<Vec<_>>();
+    let [key, name, options @ ..] = args.as_slice() else {
+        println!(
+            "Unrecognized arguments for field; expected: field <key> <field> [<kind>] [secret|plain]"
+        );
+        return Ok(true);
+    };
+    let Some(record) = db.data.get_mut(*key) else {
+        println!("There is no key '{key:}'");
+        return Ok(true);
+    };
+    if !record.value.contains_key(*name) {
+        println!("Entry {key} does not contain subkey {name}");
+        return Ok(true);
+    }
+    let mut meta = record.field_meta(name);
+    let mut sensitive = None;
+    for option in options {
+        match *option {
+            "secret" => sensitive = Some(true),
+            "plain" => sensitive = Some(false),
+            kind => match FieldKind::from_str(kind) {
+                Ok(kind) => meta = FieldMeta::of_kind(kind),
+                Err(e) => {
+                    println!("{e}");
+                    return Ok(true);
+                }
+            },
+        }
+    }
+    if let Some(sensitive) = sensitive {
+        meta.sensitive = sensitive;
+    }
+    if !options.is_empty() {
+        record.set_field_meta(name, meta);
+        db.save()?;
+    }
+    let secrecy = if meta.sensitive { "secret" } else { "plain" };
+    println!("{name}: {}, {secrecy}", meta.kind);
+    Ok(true)
+}
+
+/// The attachment is named after the file, unless a name is given.
+fn attach_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let args = args_line.split_whitespace().collect::<Vec<_>>();
+    let (key, file, name) = match args.as_slice() {
+        [key, file] => {
+            let name = PathBuf::from(file)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            let Some(name) = name else {
+                println!("Unable to name the attachment after {file}; give a name");
+                return Ok(true);
+            };
+            (*key, *file, name)
+        }
+        [key, file, name] => (*key, *file, (*name).to_string()),
+        _ => {
+            println!("Unrecognized arguments for attach; expected: attach <key> <file> [<name>]");
+            return Ok(true);
+        }
+    };
+    if !db.data.contains_key(key) {
+        println!("There is no key '{key:}'");
+        return Ok(true);
+    }
+    // The size is checked before reading; the read is limited anyway, for
+    // files without a size like pipes.
+    let limit = attachment::MAX_ATTACHMENT_SIZE;
+    let mut data = Vec::new();
+    let read = std::fs::File::open(file).and_then(|f| {
+        let size = usize::try_from(f.metadata()?.len()).unwrap_or(usize::MAX);
+        if let Err(e) = attachment::check_size(size, limit) {
+            return Ok(Err(e));
         }
+        f.take(limit as u64 + 1).read_to_end(&mut data)?;
+        Ok(Ok(()))
+    });
+    match read {
+        Ok(Ok(())) => (),
+        Ok(Err(e)) => {
+            println!("error: {e}");
+            return Ok(true);
+        }
+        Err(e) => {
+            println!("error: unable to read {file}: {e}");
+            return Ok(true);
+        }
+    }
+    let size = data.len();
+    match db.attach(key, &name, data) {
+        Ok(_) => {
+            db.save()?;
+            println!("Attached {name} ({size} bytes) to '{key:}'");
+        }
+        Err(e @ Error::AttachmentTooLarge { .. }) => println!("error: {e}"),
+        Err(e) => return Err(e),
+    }
+    Ok(true)
+}
+
+/// Does not overwrite `out`; the file is only readable by the user.
+fn extract_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let args = args_line.split_whitespace().collect::<Vec<_>>();
+    let [key, name, out] = args.as_slice() else {
+        println!("Unrecognized arguments for extract; expected: extract <key> <name> <out>");
+        return Ok(true);
+    };
+    let Some(attachment) = db
+        .access(key)
+        .and_then(|record| record.attachments.get(*name))
+    else {
+        println!("'{key:}' has no attachment {name}");
+        return Ok(true);
+    };
+    let written = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(out)
+        .and_then(|mut file| file.write_all(attachment.data()));
+    match written {
+        Ok(()) => println!("Extracted {name} ({} bytes) to {out}", attachment.len()),
+        Err(e) => println!("error: unable to write {out}: {e}"),
+    }
+    Ok(true)
+}
+
+fn detach_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let args = args_line.split_whitespace().collect::<Vec<_>>();
+    let [key, name] = args.as_slice() else {
+        println!("Unrecognized arguments for detach; expected: detach <key> <name>");
+        return Ok(true);
+    };
+    let Some(record) = db.data.get_mut(*key) else {
+        println!("There is no key '{key:}'");
+        return Ok(true);
+    };
+    if record.attachments.remove(*name).is_none() {
+        println!("'{key:}' has no attachment {name}");
+        return Ok(true);
+    }
+    record.modified = Utc::now();
+    db.save()?;
+    println!("Removed attachment {name} from '{key:}'");
+    Ok(true)
+}
+
+/// An HOTP code is used up, so the database is saved with the next counter.
+fn otp_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let key = args_line.trim();
+    if key.is_empty() {
+        println!("Unrecognized arguments for otp; expected: otp <key>");
+        return Ok(true);
+    }
+    if db.access(key).is_none() {
+        println!("There is no key '{key:}'");
+        return Ok(true);
+    }
+    let read_only = db.is_read_only();
+    let Some(record) = db.data.get_mut(key) else {
+        return Ok(true);
+    };
+    if read_only
+        && otp::find_in(record).is_some_and(|(_, otp)| {
+            otp.is_ok_and(|otp| matches!(otp.kind, otp::OtpKind::Hotp { .. }))
+        })
+    {
+        println!(
+            "error: the HOTP counter of '{key:}' can not be saved: {}",
+            Error::ReadOnly
+        );
+        return Ok(true);
+    }
+    match otp::next_code(record) {
+        None => println!("'{key:}' has no one-time code field"),
+        Some(Err(e)) => println!("error: {e}"),
+        Some(Ok((code, Some(remaining)))) => println!("{code} (valid for {remaining}s)"),
+        Some(Ok((code, None))) => {
+            db.save()?;
+            println!("{code}");
+        }
+    }
+    Ok(true)
+}
+
+/// Prints a password following the given policy, the policy of the record
+/// given with `--for`, or the default one; with `--words`, a passphrase.
+fn gen_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    if let Some(words) = args_line.trim().strip_prefix("--words") {
+        match PassphrasePolicy::from_str(words) {
+            Ok(policy) => {
+                println!("{}", generator::generate_passphrase(&policy).as_str());
+                println!("Entropy: {:.0} bits", policy.entropy());
+            }
+            Err(e) => println!("error: {e}"),
+        }
+        return Ok(true);
+    }
+    let (key, words) = match args_line.trim().strip_prefix("--for") {
+        Some(rest) => {
+            let (key, words) = parse_cmd_line(rest.trim());
+            (Some(key), words)
+        }
+        None => (None, args_line.trim()),
+    };
+    let policy = if !words.is_empty() {
+        PasswordPolicy::from_str(words)
+    } else if let Some(key) = key {
+        let Some(record) = db.data.get(key) else {
+            println!("There is no key '{key:}'");
+            return Ok(true);
+        };
+        Ok(record.password_policy.unwrap_or_default())
+    } else {
+        Ok(PasswordPolicy::default())
+    };
+    match policy.and_then(|policy| Ok((generator::generate(&policy)?, policy.entropy()))) {
+        Ok((password, entropy)) => {
+            println!("{}", password.as_str());
+            println!("Entropy: {entropy:.0} bits");
+        }
+        Err(e) => println!("error: {e}"),
     }
     Ok(true)
 }
 
-fn execute_cmd(db: &mut Db, cmd_line: &str) -> std::io::Result<bool> {
+/// Without a policy, the record goes back to the default one.
+fn policy_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let (key, words) = parse_cmd_line(args_line);
+    if key.is_empty() {
+        println!("Unrecognized arguments for policy; expected: policy <key> [<policy>]");
+        return Ok(true);
+    }
+    let policy = if words.is_empty() {
+        None
+    } else {
+        match PasswordPolicy::from_str(words) {
+            Ok(policy) => Some(policy),
+            Err(e) => {
+                println!("error: {e}");
+                return Ok(true);
+            }
+        }
+    };
+    let Some(record) = db.data.get_mut(key) else {
+        println!("There is no key '{key:}'");
+        return Ok(true);
+    };
+    record.password_policy = policy;
+    record.modified = Utc::now();
+    match policy {
+        Some(policy) => println!("Passwords for '{key:}' follow: {policy}"),
+        None => println!("Passwords for '{key:}' follow the default policy"),
+    }
+    db.save()?;
+    Ok(true)
+}
+
+/// Reports weak, reused and old passwords and records without a one-time
+/// code, see [`audit`]; with `--json`, as JSON.
+fn audit_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let mut json = false;
+    let mut max_age_days = audit::DEFAULT_MAX_AGE_DAYS;
+    for arg in args_line.split_whitespace() {
+        match arg {
+            "--json" => json = true,
+            days => {
+                let Ok(days) = days.parse() else {
+                    println!(
+                        "Unrecognized arguments for audit; expected: audit [--json] [<max age in days>]"
+                    );
+                    return Ok(true);
+                };
+                max_age_days = days;
+            }
+        }
+    }
+    let report = audit::audit(&db.data, max_age_days);
+    if json {
+        let contents = serde_json::to_string_pretty(&report).expect("Report is json-serializable");
+        println!("{contents}");
+        return Ok(true);
+    }
+    println!(
+        "{} passwords in {} records",
+        report.passwords, report.records
+    );
+    if report.is_clean() {
+        println!("Nothing to report");
+    }
+    if !report.weak.is_empty() {
+        println!("Weak passwords:");
+        for weak in &report.weak {
+            let (key, field, label) = (&weak.key, &weak.field, weak.label);
+            match weak.warning {
+                Some(warning) => println!(" {key}/{field} ({label}): {warning}"),
+                None => println!(" {key}/{field} ({label})"),
+            }
+        }
+    }
+    if !report.reused.is_empty() {
+        println!("Reused passwords:");
+        for fields in &report.reused {
+            let fields = fields
+                .iter()
+                .map(|f| format!("{}/{}", f.key, f.field))
+                .collect::<Vec<_>>();
+            println!(" {}", fields.join(", "));
+        }
+    }
+    if !report.old.is_empty() {
+        println!("Not changed for more than {max_age_days} days:");
+        for old in &report.old {
+            println!(" {} ({} days)", old.key, old.days);
+        }
+    }
+    if !report.missing_two_factor.is_empty() {
+        println!("No one-time code:");
+        for key in &report.missing_two_factor {
+            println!(" {key}");
+        }
+    }
+    Ok(true)
+}
+
+/// Looks up the stored passwords in a local copy of the Have I Been Pwned
+/// list, see [`breach`].
+fn breach_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    if args_line.trim().is_empty() {
+        println!(
+            "Unrecognized arguments for breach; expected: breach <range directory|sorted file|http://mirror/range>"
+        );
+        return Ok(true);
+    }
+    let list = match HashList::from_str(args_line) {
+        Ok(list) => list,
+        Err(e) => {
+            println!("error: {e}");
+            return Ok(true);
+        }
+    };
+    let (checked, compromised) = match breach::check(&db.data, &list) {
+        Ok(result) => result,
+        Err(e) => {
+            println!("error: {list}: {e}");
+            return Ok(true);
+        }
+    };
+    println!("Checked {checked} passwords against {list}");
+    if compromised.is_empty() {
+        println!("None of them were found");
+    }
+    for entry in &compromised {
+        println!(" {}/{}: seen {} times", entry.key, entry.field, entry.count);
+    }
+    Ok(true)
+}
+
+/// Without a path, moves the record out of its folder.
+fn folder_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let (key, folder) = parse_cmd_line(args_line);
+    if key.is_empty() {
+        println!("Unrecognized arguments for folder; expected: folder <key> [<path>]");
+        return Ok(true);
+    }
+    let Some(record) = db.data.get_mut(key) else {
+        println!("There is no key '{key:}'");
+        return Ok(true);
+    };
+    record.set_folder(folder);
+    match record.folder.clone() {
+        Some(folder) => println!("Moved '{key:}' to {folder}"),
+        None => println!("Moved '{key:}' out of folders"),
+    }
+    db.save()?;
+    Ok(true)
+}
+
+enum BackupsCmd {
+    List,
+    Verify(usize),
+    Restore(usize),
+    /// Prunes once, with the stored policy or the default one
+    Prune,
+    /// Stores the policy, which also prunes after every save, and prunes
+    KeepPruning(RetentionPolicy),
+    /// Stops pruning after saves
+    StopPruning,
+}
+
+impl BackupsCmd {
+    fn parse(args_line: &str) -> Option<Self> {
+        let args = args_line.split_whitespace().collect::<Vec<_>>();
+        if args == ["prune", "off"] {
+            return Some(BackupsCmd::StopPruning);
+        }
+        let numbers = args
+            .iter()
+            .skip(1)
+            .map(|arg| usize::from_str(arg).ok())
+            .collect::<Option<Vec<_>>>()?;
+        match (args.first().copied(), numbers.as_slice()) {
+            (None | Some("list"), []) => Some(BackupsCmd::List),
+            (Some("verify"), &[n]) => Some(BackupsCmd::Verify(n)),
+            (Some("restore"), &[n]) => Some(BackupsCmd::Restore(n)),
+            (Some("prune"), []) => Some(BackupsCmd::Prune),
+            (Some("prune"), &[keep_last, keep_daily, keep_weekly]) => {
+                Some(BackupsCmd::KeepPruning(RetentionPolicy {
+                    keep_last,
+                    keep_daily,
+                    keep_weekly,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn backups_cmd(db: &mut Db, _: &str, args_line: &str) -> Result<bool, Error> {
+    let Some(cmd) = BackupsCmd::parse(args_line) else {
+        println!("Unrecognized arguments for backups; expected: backups [list|verify <n>|restore <n>|prune [<last> <daily> <weekly>|off]]");
+        return Ok(true);
+    };
+    let backups = db.backups()?;
+    let find = |n: usize| -> Option<&Backup> {
+        let backup = n.checked_sub(1).and_then(|idx| backups.get(idx));
+        if backup.is_none() {
+            println!("There is no backup {n}; see `backups list'");
+        }
+        backup
+    };
+    match cmd {
+        BackupsCmd::List => {
+            for (idx, backup) in backups.iter().enumerate() {
+                let timestamp = backup.timestamp().format("%Y-%m-%d %H:%M:%S");
+                match backup.inspect() {
+                    Ok(info) => println!(
+                        "{}: {timestamp} ({} bytes, {}{})",
+                        idx + 1,
+                        info.size,
+                        info.kdf,
+                        if info.requires_key_file {
+                            ", key file"
+                        } else {
+                            ""
+                        }
+                    ),
+                    Err(e) => println!("{}: {timestamp} (unreadable: {e})", idx + 1),
+                }
+            }
+        }
+        BackupsCmd::Verify(n) => {
+            if let Some(backup) = find(n) {
+                match db.verify_backup(backup) {
+                    Ok(count) => println!("OK, {count} keys"),
+                    Err(e) => println!("Backup is not usable: {e}"),
+                }
+            }
+        }
+        BackupsCmd::Restore(n) => {
+            if let Some(backup) = find(n) {
+                let prompt = format!(
+                    "Replace all keys with the backup from {}? (y/n) ",
+                    backup.timestamp().format("%Y-%m-%d %H:%M:%S")
+                );
+                if linenoise::input(&prompt).as_deref() == Some("y") {
+                    db.restore_backup(backup)?;
+                    println!("Restored, now storing {} keys", db.data.len());
+                }
+            }
+        }
+        BackupsCmd::Prune => {
+            let policy = db.retention_policy().unwrap_or_default();
+            let pruned = db.prune_backups(&policy)?;
+            println!("Removed {} backups", pruned.len());
+        }
+        BackupsCmd::KeepPruning(policy) => {
+            db.set_retention_policy(Some(policy))?;
+            let pruned = db.prune_backups(&policy)?;
+            println!(
+                "Removed {} backups; old backups will be removed after every save",
+                pruned.len()
+            );
+        }
+        BackupsCmd::StopPruning => {
+            db.set_retention_policy(None)?;
+            println!("Backups will no longer be removed after saves");
+        }
+    }
+    Ok(true)
+}
+
+fn execute_cmd(db: &mut Db, cmd_line: &str) -> Result<bool, Error> {
     let (cmd, args) = parse_cmd_line(cmd_line);
     if let Some(handler) = get_command_handler(cmd) {
         handler(db, cmd, args)
@@ -622,32 +1506,112 @@ fn execute_cmd(db: &mut Db, cmd_line: &str) -> std::io::Result<bool> {
     }
 }
 
-fn parse_args() -> DbLocation {
-    let args = std::env::args().skip(1).collect::<Vec<_>>();
-    if args.is_empty() {
-        DbLocation::DotLocal
-    } else {
-        let mut it = args.into_iter();
-        let s = it.next().expect("args is not empty");
-        DbLocation::SpecifiedDirectory(PathBuf::from(s))
+/// Asks what to do when the database file was changed by another program
+/// since it was loaded.
+fn resolve_conflict(db: &mut Db) -> Result<(), Error> {
+    loop {
+        let Some(answer) = linenoise::input(
+            "[r]eload and drop your changes, [o]verwrite their changes, [m]erge, [c]ancel? ",
+        ) else {
+            return Ok(());
+        };
+        match answer.trim() {
+            "r" => {
+                db.reload()?;
+                println!("Reloaded, now storing {} keys", db.data.len());
+            }
+            "o" => {
+                db.overwrite()?;
+                println!("Saved");
+            }
+            "m" => {
+                db.merge()?;
+                db.save()?;
+                println!("Merged, now storing {} keys", db.data.len());
+            }
+            "c" => println!("Not saved"),
+            _ => continue,
+        }
+        return Ok(());
+    }
+}
+
+struct CliArgs {
+    db_location: DbLocation,
+    /// Key derivation function to switch the database to
+    kdf: Option<KdfAlgorithm>,
+    /// Key file used together with the password
+    key_file: Option<PathBuf>,
+}
+
+fn parse_args() -> CliArgs {
+    let mut result = CliArgs {
+        db_location: DbLocation::DotLocal,
+        kdf: None,
+        key_file: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kdf" => match args.next().map(|s| s.parse::<KdfAlgorithm>()) {
+                Some(Ok(kdf)) => result.kdf = Some(kdf),
+                Some(Err(e)) => {
+                    println!("{e}");
+                    std::process::exit(1);
+                }
+                None => {
+                    println!("--kdf requires a value (scrypt or argon2id)");
+                    std::process::exit(1);
+                }
+            },
+            "--key-file" => {
+                let Some(path) = args.next() else {
+                    println!("--key-file requires a path");
+                    std::process::exit(1);
+                };
+                result.key_file = Some(PathBuf::from(path));
+            }
+            _ => result.db_location = DbLocation::SpecifiedDirectory(PathBuf::from(arg)),
+        }
     }
+    result
 }
 
 fn main() {
-    let db_location = parse_args();
+    let args = parse_args();
     let mut db;
-    let password = linenoise::input("Enter password: ").expect("stdio should be successful");
-    match Db::load(&db_location, &password) {
-        Ok(DbLoadResult::Loaded(loaded_db)) => {
-            db = loaded_db;
-        }
-        Ok(DbLoadResult::WrongPassword) => {
-            println!("Wrong password");
+    if let Err(e) = secret::disable_core_dumps() {
+        println!("warning: unable to disable core dumps: {e:}");
+    }
+    let password =
+        Zeroizing::new(linenoise::input("Enter password: ").expect("stdio should be successful"));
+    if !args.db_location.exists() {
+        print_strength(&strength::estimate(&password, &[]));
+    }
+    let key = match CompositeKey::new(&password, args.key_file.as_deref()) {
+        Ok(key) => key,
+        Err(e) => {
+            println!("error: unable to read key file: {e:}");
             std::process::exit(1);
         }
+    };
+    let load_result = match Db::load(&args.db_location, &key) {
+        Err(e @ Error::Locked(_)) => {
+            println!("warning: {e:}; opening read-only");
+            Db::load_read_only(&args.db_location, &key)
+        }
+        result => result,
+    };
+    match load_result {
+        Ok(loaded_db) => {
+            db = loaded_db;
+            if let Some(kdf) = args.kdf {
+                db.set_kdf(kdf);
+            }
+        }
         Err(e) => {
             println!("error: {e:}");
-            std::process::exit(1);
+            std::process::exit(e.exit_code());
         }
     }
     linenoise::clear_screen();
@@ -656,12 +1620,79 @@ fn main() {
         if !cmd.is_empty() {
             match execute_cmd(&mut db, &cmd) {
                 Ok(true) => {}
-                Ok(false) => return,
+                Ok(false) => break,
+                Err(e @ Error::ReadOnly) => {
+                    println!("error: {e:}; changes are not saved");
+                }
+                Err(e @ Error::Conflict) => {
+                    println!("error: {e:}");
+                    if let Err(e) = resolve_conflict(&mut db) {
+                        println!("error: {e:}");
+                        std::process::exit(e.exit_code());
+                    }
+                }
                 Err(e) => {
                     println!("error: {e:}");
-                    std::process::exit(1);
+                    std::process::exit(e.exit_code());
                 }
             }
+            if let Some(e) = db.take_prune_error() {
+                println!("warning: saved, but unable to delete old backups: {e:}");
+            }
         }
     }
+    if let Err(e) = db.save_access_times() {
+        println!("warning: access times are not saved: {e:}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn dump_and_import_keep_history() {
+        let dir = std::env::temp_dir().join(format!("cred-man-test-{}-dump", std::process::id()));
+        let key = CompositeKey::new("pw", None).expect("key");
+        let mut db = Db::load(&DbLocation::SpecifiedDirectory(dir.join("a")), &key).expect("load");
+        let mut record = DbRecord::new("k".to_string());
+        record
+            .value
+            .insert("password".to_string(), "old".to_string());
+        record.new_revision();
+        record
+            .value
+            .insert("password".to_string(), "new".to_string());
+        let timestamp = record.history[0].timestamp;
+        db.data.insert("k".to_string(), record);
+        let dump = dir.join("dump.json");
+        dump_cmd(&mut db, "dump", &dump.to_string_lossy()).expect("dump");
+
+        let mut imported =
+            Db::load(&DbLocation::SpecifiedDirectory(dir.join("b")), &key).expect("load");
+        import_from(&mut imported, &dump.to_string_lossy()).expect("import");
+        let history = &imported.data["k"].history;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].value["password"], "old");
+        assert_eq!(history[0].timestamp.timestamp(), timestamp.timestamp());
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn import_with_a_bad_attachment_changes_nothing() {
+        let dir = std::env::temp_dir().join(format!("cred-man-test-{}-import", std::process::id()));
+        let key = CompositeKey::new("pw", None).expect("key");
+        let mut db = Db::load(&DbLocation::SpecifiedDirectory(dir.clone()), &key).expect("load");
+        let dump = dir.join("dump.json");
+        fs::write(
+            &dump,
+            r#"[{"key":"a","value":{}},
+                {"key":"b","value":{},"attachments":{"f":"not base64!"}}]"#,
+        )
+        .expect("write");
+        assert!(import_from(&mut db, &dump.to_string_lossy()).is_err());
+        assert!(db.data.is_empty());
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+    }
 }
@@ -0,0 +1,25 @@
+#![no_main]
+
+use cred_man_lib::encrypted_file::{self, CompositeKey, KdfParams};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(content) = encrypted_file::parse_bytes(data) else {
+        return;
+    };
+    // The header picks the KDF cost; skip inputs that would make every run
+    // take seconds and gigabytes.
+    let cheap = match content.kdf() {
+        KdfParams::Scrypt { log_n, r, p } => log_n <= 10 && r <= 8 && p <= 2,
+        KdfParams::Argon2id {
+            m_cost,
+            t_cost,
+            p_cost,
+        } => m_cost <= 1024 && t_cost <= 2 && p_cost <= 2,
+    };
+    if !cheap {
+        return;
+    }
+    let key = CompositeKey::new("password", None).expect("no key file to read");
+    let _ = encrypted_file::decrypt(&content, &key);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use cred_man_lib::encrypted_file;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = encrypted_file::parse_bytes(data);
+});
@@ -22,11 +22,14 @@
     clippy::unnecessary_wraps
 )]
 
-use cred_man_lib::{Db, DbLoadResult, DbLocation};
+use cred_man_lib::{otp, secret, CompositeKey, Db, DbLocation, Error, KdfAlgorithm};
 use gtk::prelude::*;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
+use zeroize::Zeroizing;
 
 const BUILDER_UI: &str = include_str!("cred_man_gtk.ui");
 
@@ -35,14 +38,24 @@ struct Ui {
     tree_credentials: gtk::TreeView,
     store_credentials: gtk::TreeStore,
     btn_unlock: gtk::ToolButton,
+    btn_change_password: gtk::ToolButton,
     dlg_password: gtk::Dialog,
     entry_password: gtk::Entry,
+    dlg_change_password: gtk::Dialog,
+    entry_current_password: gtk::Entry,
+    entry_new_password: gtk::Entry,
+    entry_repeat_password: gtk::Entry,
     entry_search_credentials: gtk::Entry,
 
     dialog_credinfo: gtk::Dialog,
     label_credinfo_key: gtk::Label,
     label_credinfo_attr: gtk::Label,
     entry_credinfo_value: gtk::Entry,
+    /// Shown below `entry_credinfo_value` for multi-line values
+    scroll_credinfo_value: gtk::ScrolledWindow,
+    text_credinfo_value: gtk::TextView,
+    /// Live code when the attribute is the record's one-time code field
+    label_credinfo_otp: gtk::Label,
 
     db: Option<Db>,
     credinfo_value: Option<(String, String, String)>,
@@ -50,7 +63,11 @@ struct Ui {
 
 impl Ui {
     #[allow(clippy::too_many_lines)]
-    pub fn new(db_location: DbLocation) -> Rc<RefCell<Self>> {
+    pub fn new(
+        db_location: DbLocation,
+        kdf: Option<KdfAlgorithm>,
+        key_file: Option<PathBuf>,
+    ) -> Rc<RefCell<Self>> {
         let b = gtk::Builder::new();
 
         b.add_from_string(BUILDER_UI)
@@ -70,6 +87,10 @@ impl Ui {
 
         let btn_unlock: gtk::ToolButton = b.object("btnUnlock").expect("Unable to find btnUnlock");
 
+        let btn_change_password: gtk::ToolButton = b
+            .object("btnChangePassword")
+            .expect("Unable to find btnChangePassword");
+
         let dlg_password: gtk::Dialog =
             b.object("dlgPassword").expect("Unable to find dlgPassword");
 
@@ -77,6 +98,19 @@ impl Ui {
             .object("entryPassword")
             .expect("Unable to find entryPassword");
 
+        let dlg_change_password: gtk::Dialog = b
+            .object("dlgChangePassword")
+            .expect("Unable to find dlgChangePassword");
+        let entry_current_password: gtk::Entry = b
+            .object("entryCurrentPassword")
+            .expect("Unable to find entryCurrentPassword");
+        let entry_new_password: gtk::Entry = b
+            .object("entryNewPassword")
+            .expect("Unable to find entryNewPassword");
+        let entry_repeat_password: gtk::Entry = b
+            .object("entryRepeatPassword")
+            .expect("Unable to find entryRepeatPassword");
+
         let entry_search_credentials: gtk::Entry = b
             .object("entrySearchCredentials")
             .expect("Unable to find entrySearchCredentials");
@@ -93,19 +127,36 @@ impl Ui {
         let entry_credinfo_value: gtk::Entry = b
             .object("entry_credinfo_value")
             .expect("Unable to find entry_credinfo_value");
+        let scroll_credinfo_value: gtk::ScrolledWindow = b
+            .object("scroll_credinfo_value")
+            .expect("Unable to find scroll_credinfo_value");
+        let text_credinfo_value: gtk::TextView = b
+            .object("text_credinfo_value")
+            .expect("Unable to find text_credinfo_value");
+        let label_credinfo_otp: gtk::Label = b
+            .object("label_credinfo_otp")
+            .expect("Unable to find label_credinfo_otp");
 
         let result = Rc::new(RefCell::new(Ui {
             window: w.clone(),
             tree_credentials: tree_credentials.clone(),
             store_credentials,
             btn_unlock: btn_unlock.clone(),
+            btn_change_password: btn_change_password.clone(),
             dlg_password: dlg_password.clone(),
             entry_password,
+            dlg_change_password: dlg_change_password.clone(),
+            entry_current_password,
+            entry_new_password,
+            entry_repeat_password,
             entry_search_credentials: entry_search_credentials.clone(),
             dialog_credinfo,
             label_credinfo_key,
             label_credinfo_attr,
             entry_credinfo_value: entry_credinfo_value.clone(),
+            scroll_credinfo_value,
+            text_credinfo_value,
+            label_credinfo_otp,
             db: None,
             credinfo_value: None,
         }));
@@ -124,10 +175,32 @@ impl Ui {
         let result2 = result.clone();
         dlg_password.connect_response(move |_, response| {
             if response == gtk::ResponseType::Other(5) {
-                let password = result2.borrow().entry_password.text().as_str().to_owned();
-
-                match Db::load(&db_location, &password) {
-                    Ok(DbLoadResult::Loaded(db)) => {
+                let password =
+                    Zeroizing::new(result2.borrow().entry_password.text().as_str().to_owned());
+
+                let load_result = CompositeKey::new(&password, key_file.as_deref())
+                    .map_err(Error::from)
+                    .and_then(|key| match Db::load(&db_location, &key) {
+                        Err(e @ Error::Locked(_)) => {
+                            let dlg = gtk::MessageDialog::new(
+                                Some(&result2.borrow().dlg_password),
+                                gtk::DialogFlags::MODAL,
+                                gtk::MessageType::Warning,
+                                gtk::ButtonsType::Close,
+                                &format!("{e}; opening read-only"),
+                            );
+                            dlg.run();
+                            dlg.close();
+                            Db::load_read_only(&db_location, &key)
+                        }
+                        result => result,
+                    });
+                match load_result {
+                    Ok(mut db) => {
+                        if let Some(kdf) = kdf {
+                            db.set_kdf(kdf);
+                        }
+                        let read_only = db.is_read_only();
                         result2.borrow_mut().db = Some(db);
 
                         result2
@@ -136,28 +209,21 @@ impl Ui {
                             .set_sensitive(true);
                         result2.borrow().tree_credentials.set_sensitive(true);
                         result2.borrow().btn_unlock.set_sensitive(false);
+                        result2
+                            .borrow()
+                            .btn_change_password
+                            .set_sensitive(!read_only);
                         result2.borrow().dlg_password.hide();
 
                         Ui::refresh_tree(&result2);
                     }
-                    Ok(DbLoadResult::WrongPassword) => {
-                        let dlg = gtk::MessageDialog::new(
-                            Some(&result2.borrow().dlg_password),
-                            gtk::DialogFlags::MODAL,
-                            gtk::MessageType::Error,
-                            gtk::ButtonsType::Close,
-                            "Wrong password",
-                        );
-                        dlg.run();
-                        dlg.close();
-                    }
                     Err(e) => {
                         let dlg = gtk::MessageDialog::new(
                             Some(&result2.borrow().dlg_password),
                             gtk::DialogFlags::MODAL,
                             gtk::MessageType::Error,
                             gtk::ButtonsType::Close,
-                            &format!("error: {e:}"),
+                            &e.to_string(),
                         );
                         dlg.run();
                         dlg.close();
@@ -166,19 +232,42 @@ impl Ui {
             }
         });
 
+        let result2 = result.clone();
+        btn_change_password.connect_clicked(move |_| {
+            let ui = result2.borrow();
+            ui.entry_current_password.set_text("");
+            ui.entry_new_password.set_text("");
+            ui.entry_repeat_password.set_text("");
+            ui.dlg_change_password.show_all();
+        });
+
+        let result2 = result.clone();
+        dlg_change_password.connect_delete_event(move |_, _| {
+            result2.borrow().dlg_change_password.hide();
+            glib::Propagation::Stop
+        });
+
+        let result2 = result.clone();
+        dlg_change_password.connect_response(move |_, response| {
+            if response == gtk::ResponseType::Other(5) {
+                Ui::change_password(&result2);
+            } else {
+                result2.borrow().dlg_change_password.hide();
+            }
+        });
+
         let result2 = result.clone();
         tree_credentials.connect_row_activated(move |_, path, _| {
             let store = result2.borrow().store_credentials.clone();
             let iter = store.iter(path).expect("path was activated");
-            let parent_iter = match store.iter_parent(&iter) {
-                None => return,
-                Some(it) => it,
+            // Only attribute rows have the key of their record set.
+            let Some(key) = store
+                .value(&iter, 1)
+                .get::<Option<String>>()
+                .expect("type is a string")
+            else {
+                return;
             };
-
-            let key = store
-                .value(&parent_iter, 0)
-                .get::<String>()
-                .expect("type is a string");
             let attr_name = store
                 .value(&iter, 0)
                 .get::<String>()
@@ -211,6 +300,43 @@ impl Ui {
         result
     }
 
+    fn change_password(ui_ref: &Rc<RefCell<Self>>) {
+        let ui = &mut *ui_ref.borrow_mut();
+        let current = Zeroizing::new(ui.entry_current_password.text().as_str().to_owned());
+        let new_password = Zeroizing::new(ui.entry_new_password.text().as_str().to_owned());
+        let repeated = Zeroizing::new(ui.entry_repeat_password.text().as_str().to_owned());
+        let db = ui.db.as_mut().expect("db is open at this moment");
+
+        let error = if !db.verify_password(&current) {
+            Some("Wrong current password".to_string())
+        } else if new_password.is_empty() {
+            Some("New password must not be empty".to_string())
+        } else if new_password != repeated {
+            Some("Passwords do not match".to_string())
+        } else {
+            db.change_password(&new_password)
+                .err()
+                .map(|e| format!("error: {e:}"))
+        };
+
+        match error {
+            Some(message) => {
+                let dlg = gtk::MessageDialog::new(
+                    Some(&ui.dlg_change_password),
+                    gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Error,
+                    gtk::ButtonsType::Close,
+                    &message,
+                );
+                dlg.run();
+                dlg.close();
+            }
+            None => {
+                ui.dlg_change_password.hide();
+            }
+        }
+    }
+
     fn refresh_tree(ui: &Rc<RefCell<Self>>) {
         let store_credentials = ui.borrow().store_credentials.clone();
         let search_criteria = ui
@@ -228,6 +354,8 @@ impl Ui {
 
         store_credentials.clear();
 
+        // Rows of the folders created so far, by path.
+        let mut folders: BTreeMap<String, gtk::TreeIter> = BTreeMap::new();
         for (name, record) in &ui
             .borrow()
             .db
@@ -240,11 +368,26 @@ impl Ui {
                 Some(key) => name.contains(key),
             };
             if is_match {
-                let it = store_credentials.append(None);
+                let mut parent: Option<gtk::TreeIter> = None;
+                let mut path = String::new();
+                for component in record.folder.iter().flat_map(|folder| folder.split('/')) {
+                    if !path.is_empty() {
+                        path.push('/');
+                    }
+                    path.push_str(component);
+                    let folder_it = *folders.entry(path.clone()).or_insert_with(|| {
+                        let it = store_credentials.append(parent.as_ref());
+                        store_credentials.set_value(&it, 0, &glib::Value::from(component));
+                        it
+                    });
+                    parent = Some(folder_it);
+                }
+                let it = store_credentials.append(parent.as_ref());
                 store_credentials.set_value(&it, 0, &glib::Value::from(&name));
                 for attr_name in record.value.keys() {
                     let it2 = store_credentials.append(Some(&it));
                     store_credentials.set_value(&it2, 0, &glib::Value::from(&attr_name));
+                    store_credentials.set_value(&it2, 1, &glib::Value::from(&name));
                 }
             }
         }
@@ -253,6 +396,7 @@ impl Ui {
     fn show_attr(ui_ref: &Rc<RefCell<Self>>, key: &str, attr: &str) {
         let dialog_credinfo;
         let label_credinfo_key;
+        let otp_timer;
 
         {
             let ui = &mut *ui_ref.borrow_mut();
@@ -264,10 +408,8 @@ impl Ui {
 
             let db = ui.db.as_ref().expect("db is open at this moment");
 
-            let value = db
-                .data
-                .get(key)
-                .expect("key from tree is in db")
+            let record = db.data.get(key).expect("key from tree is in db");
+            let value = record
                 .value
                 .get(attr)
                 .expect("key and attr from tree is in db");
@@ -276,15 +418,108 @@ impl Ui {
 
             label_credinfo_key.set_text(key);
             label_credinfo_attr.set_text(attr);
-            entry_credinfo_value.set_text("<click \"refresh\" to reveal>");
+            ui.scroll_credinfo_value.set_visible(value.contains('\n'));
+            if record.is_sensitive(attr) {
+                entry_credinfo_value.set_text("<click \"refresh\" to reveal>");
+            } else {
+                ui.show_credinfo_value(value);
+            }
+            otp_timer = ui.show_credinfo_otp(key, attr);
         }
 
         dialog_credinfo.run();
         dialog_credinfo.hide();
+        if let Some(otp_timer) = otp_timer {
+            otp_timer.remove();
+        }
 
         {
             let ui = &mut *ui_ref.borrow_mut();
             ui.credinfo_value = None;
+            ui.entry_credinfo_value.set_text("");
+            ui.credinfo_buffer().set_text("");
+            ui.label_credinfo_otp.set_text("");
+            ui.label_credinfo_otp.set_visible(false);
+        }
+    }
+
+    /// Shows the code if `attr` is the record's one-time code field; a TOTP
+    /// code is kept current by the returned timer. An HOTP code is used up
+    /// by showing it, so the database is saved with the next counter; it is
+    /// not shown if that is not possible.
+    fn show_credinfo_otp(&mut self, key: &str, attr: &str) -> Option<glib::SourceId> {
+        let db = self.db.as_mut().expect("db is open at this moment");
+        let read_only = db.is_read_only();
+        let record = db.data.get_mut(key).expect("key from tree is in db");
+        let (name, otp) = otp::find_in(record)?;
+        if name != attr {
+            return None;
+        }
+        let label = self.label_credinfo_otp.clone();
+        label.set_visible(true);
+        let otp = match otp {
+            Ok(otp) => otp,
+            Err(e) => {
+                label.set_text(&format!("One-time code: {e}"));
+                return None;
+            }
+        };
+        if let otp::OtpKind::Hotp { .. } = otp.kind {
+            if read_only {
+                label.set_text(&format!(
+                    "One-time code: not shown, the counter can not be saved: {}",
+                    Error::ReadOnly
+                ));
+                return None;
+            }
+            let text = match otp::next_code(record) {
+                Some(Ok((code, _))) => match Ui::save(&self.window, db) {
+                    Ok(true) => format!("One-time code: {code}"),
+                    Ok(false) => "One-time code: not shown, the counter is not saved".to_string(),
+                    Err(e) => format!("One-time code: not shown, unable to save the counter: {e}"),
+                },
+                Some(Err(e)) => format!("One-time code: {e}"),
+                None => String::new(),
+            };
+            label.set_text(&text);
+            return None;
+        }
+        let update = move || {
+            if let (code, Some(remaining)) = otp.now() {
+                label.set_text(&format!("One-time code: {code} ({remaining}s)"));
+            }
+        };
+        update();
+        Some(glib::timeout_add_local(Duration::from_secs(1), move || {
+            update();
+            glib::ControlFlow::Continue
+        }))
+    }
+
+    /// Saves the database, asking what to do if another program saved it
+    /// since it was loaded: merge their changes, or overwrite them. Returns
+    /// `false` if the user cancelled.
+    fn save(window: &gtk::Window, db: &mut Db) -> Result<bool, Error> {
+        match db.save() {
+            Err(Error::Conflict) => {}
+            result => return result.map(|()| true),
+        }
+        let dlg = gtk::MessageDialog::new(
+            Some(window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Warning,
+            gtk::ButtonsType::None,
+            &Error::Conflict.to_string(),
+        );
+        dlg.add_button("Merge", gtk::ResponseType::Other(1));
+        dlg.add_button("Overwrite", gtk::ResponseType::Other(2));
+        dlg.add_button("Cancel", gtk::ResponseType::Cancel);
+        let response = dlg.run();
+        dlg.close();
+        match response {
+            gtk::ResponseType::Other(1) => db.merge().and_then(|()| db.save()).map(|()| true),
+            gtk::ResponseType::Other(2) => db.overwrite().map(|()| true),
+            _ => Ok(false),
         }
     }
 
@@ -296,7 +531,23 @@ impl Ui {
             .expect("value should be available at this moment")
             .2
             .clone();
-        ui.entry_credinfo_value.set_text(&value);
+        ui.show_credinfo_value(&value);
+    }
+
+    fn show_credinfo_value(&self, value: &str) {
+        if value.contains('\n') {
+            self.entry_credinfo_value
+                .set_text("<multi-line value, shown below>");
+            self.credinfo_buffer().set_text(value);
+        } else {
+            self.entry_credinfo_value.set_text(value);
+        }
+    }
+
+    fn credinfo_buffer(&self) -> gtk::TextBuffer {
+        self.text_credinfo_value
+            .buffer()
+            .expect("text view has a buffer")
     }
 
     fn credinfo_copy(ui_ref: &Rc<RefCell<Self>>) {
@@ -323,23 +574,57 @@ impl Ui {
     }
 }
 
-fn parse_args() -> DbLocation {
-    let args = std::env::args().skip(1).collect::<Vec<_>>();
-    if args.is_empty() {
-        DbLocation::DotLocal
-    } else {
-        let mut it = args.into_iter();
-        let s = it.next().expect("args is not empty");
-        DbLocation::SpecifiedDirectory(PathBuf::from(s))
+struct CliArgs {
+    db_location: DbLocation,
+    /// Key derivation function to switch the database to
+    kdf: Option<KdfAlgorithm>,
+    /// Key file used together with the password
+    key_file: Option<PathBuf>,
+}
+
+fn parse_args() -> CliArgs {
+    let mut result = CliArgs {
+        db_location: DbLocation::DotLocal,
+        kdf: None,
+        key_file: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--kdf" => match args.next().map(|s| s.parse::<KdfAlgorithm>()) {
+                Some(Ok(kdf)) => result.kdf = Some(kdf),
+                Some(Err(e)) => {
+                    println!("{e}");
+                    std::process::exit(1);
+                }
+                None => {
+                    println!("--kdf requires a value (scrypt or argon2id)");
+                    std::process::exit(1);
+                }
+            },
+            "--key-file" => {
+                let Some(path) = args.next() else {
+                    println!("--key-file requires a path");
+                    std::process::exit(1);
+                };
+                result.key_file = Some(PathBuf::from(path));
+            }
+            _ => result.db_location = DbLocation::SpecifiedDirectory(PathBuf::from(arg)),
+        }
     }
+    result
 }
 
 fn main() {
-    let db_location = parse_args();
+    let args = parse_args();
+
+    if let Err(e) = secret::disable_core_dumps() {
+        println!("warning: unable to disable core dumps: {e:}");
+    }
 
     gtk::init().expect("Unable to initialize Gtk+");
 
-    let _ = Ui::new(db_location);
+    let _ = Ui::new(args.db_location, args.kdf, args.key_file);
 
     gtk::main();
 }
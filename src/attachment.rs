@@ -0,0 +1,121 @@
+//! Small files (certificates, key stores, license files) kept in a record.
+//!
+//! Attachments are stored base64-encoded inside the encrypted database, so
+//! they are limited in size: the whole database is read, decrypted and
+//! written on every load and save.
+
+use crate::Error;
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// Largest attachment, in bytes.
+pub const MAX_ATTACHMENT_SIZE: usize = 1 << 20;
+/// Largest total size of the attachments of all records, in bytes.
+pub const MAX_TOTAL_ATTACHMENTS_SIZE: usize = 16 << 20;
+
+pub struct Attachment {
+    pub added: DateTime<Utc>,
+    data: Vec<u8>,
+}
+
+impl Attachment {
+    /// Fails with [`Error::AttachmentTooLarge`] if `data` is larger than
+    /// [`MAX_ATTACHMENT_SIZE`].
+    pub fn new(data: Vec<u8>) -> Result<Attachment, Error> {
+        check_size(data.len(), MAX_ATTACHMENT_SIZE)?;
+        Ok(Attachment {
+            added: Utc::now(),
+            data,
+        })
+    }
+
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+/// Fails with [`Error::AttachmentTooLarge`] if `size` is over `limit`.
+pub fn check_size(size: usize, limit: usize) -> Result<(), Error> {
+    if size > limit {
+        return Err(Error::AttachmentTooLarge { size, limit });
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "AttachmentRepr")]
+pub(crate) struct AttachmentDTO {
+    /// Missing in exports made by older versions
+    added: Option<String>,
+    /// Base64
+    data: String,
+}
+
+/// Older exports only have the base64 data.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AttachmentRepr {
+    Full { added: String, data: String },
+    Data(String),
+}
+
+impl From<AttachmentRepr> for AttachmentDTO {
+    fn from(repr: AttachmentRepr) -> AttachmentDTO {
+        match repr {
+            AttachmentRepr::Full { added, data } => AttachmentDTO {
+                added: Some(added),
+                data,
+            },
+            AttachmentRepr::Data(data) => AttachmentDTO { added: None, data },
+        }
+    }
+}
+
+impl Drop for AttachmentDTO {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+impl AttachmentDTO {
+    pub(crate) fn from_attachment(attachment: &Attachment) -> AttachmentDTO {
+        AttachmentDTO {
+            added: Some(crate::format_dto_timestamp(attachment.added)),
+            data: Base64::encode_string(&attachment.data),
+        }
+    }
+
+    /// Attachments without a time of their own count as added at `fallback`.
+    pub(crate) fn to_attachment(
+        &self,
+        name: &str,
+        fallback: DateTime<Utc>,
+    ) -> Result<Attachment, Error> {
+        let data = Base64::decode_vec(&self.data)
+            .map_err(|e| Error::Corrupt(format!("invalid attachment \"{name}\": {e}")))?;
+        let added = match &self.added {
+            Some(added) => crate::parse_dto_timestamp(added)?,
+            None => fallback,
+        };
+        Ok(Attachment { added, data })
+    }
+}
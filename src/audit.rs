@@ -0,0 +1,223 @@
+//! A security report over all records: weak passwords, passwords used for
+//! more than one record, passwords not changed for a long time, and records
+//! without a second factor.
+//!
+//! Passwords are the fields of kind [`FieldKind::Password`]. The report
+//! names records and fields, never the passwords themselves, so that it can
+//! be shown or exported as it is.
+
+use crate::strength;
+use crate::{otp, DbRecord, FieldKind};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Passwords with this score or lower (see [`strength::Strength::score`])
+/// are reported as weak.
+pub const MAX_WEAK_SCORE: u8 = 2;
+/// Default age in days after which a password should be changed.
+pub const DEFAULT_MAX_AGE_DAYS: u32 = 365;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    /// How many records there are
+    pub records: usize,
+    /// How many password fields there are in all records
+    pub passwords: usize,
+    pub weak: Vec<WeakPassword>,
+    /// Passwords shared by several records, one entry per password
+    pub reused: Vec<Vec<FieldRef>>,
+    pub old: Vec<OldPassword>,
+    /// Keys of records with a password but no one-time code field, see
+    /// [`otp::find_in`]
+    pub missing_two_factor: Vec<String>,
+}
+
+impl Report {
+    /// Whether there is nothing to report.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.weak.is_empty()
+            && self.reused.is_empty()
+            && self.old.is_empty()
+            && self.missing_two_factor.is_empty()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FieldRef {
+    pub key: String,
+    pub field: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WeakPassword {
+    pub key: String,
+    pub field: String,
+    /// 0 to [`MAX_WEAK_SCORE`]
+    pub score: u8,
+    /// See [`strength::Strength::label`]
+    pub label: &'static str,
+    pub warning: Option<&'static str>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OldPassword {
+    pub key: String,
+    /// Days since the oldest of the record's passwords was set, see
+    /// [`password_set`]
+    pub days: i64,
+}
+
+/// When the current value of the field `name` was set, as far as the
+/// history of `record` goes back. Changes to other fields or to the tags,
+/// folder and such don't count.
+#[must_use]
+pub fn password_set(record: &DbRecord, name: &str) -> DateTime<Utc> {
+    let current = record.value.get(name);
+    let mut set = record.value_changed;
+    for revision in record.history.iter().rev() {
+        if revision.value.get(name) != current {
+            return set;
+        }
+        set = revision.timestamp;
+    }
+    record.created
+}
+
+/// Audits `data` (see [`crate::Db::data`]); passwords set more than
+/// `max_age_days` days ago count as old.
+#[must_use]
+pub fn audit(data: &BTreeMap<String, DbRecord>, max_age_days: u32) -> Report {
+    let now = Utc::now();
+    let mut report = Report {
+        records: data.len(),
+        ..Report::default()
+    };
+    let mut by_password: HashMap<&str, Vec<FieldRef>> = HashMap::new();
+    for record in data.values() {
+        let passwords = record
+            .value
+            .iter()
+            .filter(|(name, value)| {
+                !value.is_empty() && record.field_meta(name).kind == FieldKind::Password
+            })
+            .collect::<Vec<_>>();
+        if passwords.is_empty() {
+            continue;
+        }
+        report.passwords += passwords.len();
+        let mut oldest = now;
+        for (name, value) in passwords {
+            oldest = oldest.min(password_set(record, name));
+            let strength = strength::estimate_in(record, name, value);
+            if strength.score <= MAX_WEAK_SCORE {
+                report.weak.push(WeakPassword {
+                    key: record.key.clone(),
+                    field: name.clone(),
+                    score: strength.score,
+                    label: strength.label(),
+                    warning: strength.warning,
+                });
+            }
+            by_password
+                .entry(value.as_str())
+                .or_default()
+                .push(FieldRef {
+                    key: record.key.clone(),
+                    field: name.clone(),
+                });
+        }
+        let days = (now - oldest).num_days();
+        if days > i64::from(max_age_days) {
+            report.old.push(OldPassword {
+                key: record.key.clone(),
+                days,
+            });
+        }
+        if otp::find_in(record).is_none() {
+            report.missing_two_factor.push(record.key.clone());
+        }
+    }
+    report.reused = by_password
+        .into_values()
+        .filter(|fields| fields.iter().any(|field| field.key != fields[0].key))
+        .collect();
+    report.reused.sort();
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    fn record(key: &str, fields: &[(&str, &str)]) -> (String, DbRecord) {
+        let mut record = DbRecord::new(key.to_string());
+        for (name, value) in fields {
+            record
+                .value
+                .insert((*name).to_string(), (*value).to_string());
+        }
+        (key.to_string(), record)
+    }
+
+    #[test]
+    fn finds_weak_reused_old_and_single_factor_passwords() {
+        let strong = "correct-horse-battery-staple";
+        let mut data = BTreeMap::from([
+            record("bank", &[("password", "password1")]),
+            record(
+                "mail",
+                &[("password", strong), ("totp", "JBSWY3DPEHPK3PXP")],
+            ),
+            record("shop", &[("user", "me"), ("password", strong)]),
+            record("notes", &[("note", "password1")]),
+        ]);
+        data.get_mut("shop").expect("inserted").created -= Duration::days(400);
+
+        let report = audit(&data, DEFAULT_MAX_AGE_DAYS);
+        assert_eq!((report.records, report.passwords), (4, 3));
+        let weak = report
+            .weak
+            .iter()
+            .map(|w| w.key.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(weak, ["bank"]);
+        let reused = report
+            .reused
+            .iter()
+            .map(|fields| fields.iter().map(|f| f.key.as_str()).collect::<Vec<_>>());
+        assert_eq!(reused.collect::<Vec<_>>(), [["mail", "shop"]]);
+        assert_eq!(report.old.len(), 1);
+        assert_eq!(
+            (report.old[0].key.as_str(), report.old[0].days),
+            ("shop", 400)
+        );
+        assert_eq!(report.missing_two_factor, ["bank", "shop"]);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn only_password_changes_reset_the_age() {
+        let (_, mut record) = record("shop", &[("user", "me"), ("password", "old")]);
+        record.created -= Duration::days(400);
+        record.value_changed = record.created;
+        record.add_tag("shopping");
+        assert_eq!(password_set(&record, "password"), record.created);
+
+        record.new_revision();
+        record.value.insert("user".to_string(), "you".to_string());
+        assert_eq!(password_set(&record, "password"), record.created);
+
+        record.new_revision();
+        record
+            .value
+            .insert("password".to_string(), "new".to_string());
+        let changed = record.value_changed;
+        record.new_revision();
+        record.value.insert("user".to_string(), "me".to_string());
+        record.add_tag("online");
+        assert_eq!(password_set(&record, "password"), changed);
+    }
+}
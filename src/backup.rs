@@ -0,0 +1,224 @@
+//! Backups made by [`Db::save`](crate::Db::save): every save keeps the
+//! previous `keys.db` as `keys.backup.YYYYMMDD_HHMMSS.db` next to it.
+
+use crate::encrypted_file::{self, KdfAlgorithm};
+use crate::Error;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub(crate) const BACKUP_PREFIX: &str = "keys.backup.";
+pub(crate) const BACKUP_SUFFIX: &str = ".db";
+pub(crate) const BACKUP_TIME_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// Which backups survive pruning. A backup is kept if it is one of the
+/// `keep_last` newest ones, or the newest one of one of the `keep_daily`
+/// latest days (or `keep_weekly` latest weeks) that have backups.
+///
+/// Backups are only pruned after saves once the user has chosen a policy,
+/// see [`Db::set_retention_policy`](crate::Db::set_retention_policy); it is
+/// kept in `keys.retention.json` next to the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    pub keep_last: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            keep_last: 10,
+            keep_daily: 7,
+            keep_weekly: 4,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Splits `backups` (newest first, as returned by `Db::backups`) into the
+    /// ones to keep and the ones to delete.
+    #[must_use]
+    pub fn apply(&self, backups: Vec<Backup>) -> (Vec<Backup>, Vec<Backup>) {
+        let mut days: HashSet<NaiveDate> = HashSet::new();
+        let mut weeks: HashSet<(i32, u32)> = HashSet::new();
+        let mut keep = Vec::new();
+        let mut prune = Vec::new();
+        for (idx, backup) in backups.into_iter().enumerate() {
+            let date = backup.timestamp.date();
+            let week = (date.iso_week().year(), date.iso_week().week());
+            let mut kept = idx < self.keep_last;
+            if !days.contains(&date) && days.len() < self.keep_daily {
+                days.insert(date);
+                kept = true;
+            }
+            if !weeks.contains(&week) && weeks.len() < self.keep_weekly {
+                weeks.insert(week);
+                kept = true;
+            }
+            if kept {
+                keep.push(backup);
+            } else {
+                prune.push(backup);
+            }
+        }
+        (keep, prune)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Backup {
+    path: PathBuf,
+    timestamp: NaiveDateTime,
+}
+
+/// What can be told about a backup without the password.
+#[derive(Clone, Copy, Debug)]
+pub struct BackupInfo {
+    pub size: u64,
+    pub kdf: KdfAlgorithm,
+    pub requires_key_file: bool,
+}
+
+impl Backup {
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// When the backup was made (local time).
+    #[must_use]
+    pub fn timestamp(&self) -> NaiveDateTime {
+        self.timestamp
+    }
+
+    /// Reads the file header; fails if it is not a readable database.
+    pub fn inspect(&self) -> Result<BackupInfo, Error> {
+        let size = fs::metadata(&self.path)?.len();
+        let data = encrypted_file::parse_file(&self.path)?;
+        Ok(BackupInfo {
+            size,
+            kdf: data.kdf().algorithm(),
+            requires_key_file: data.requires_key_file(),
+        })
+    }
+
+    fn from_path(path: PathBuf) -> Option<Backup> {
+        let timestamp = path
+            .file_name()?
+            .to_str()?
+            .strip_prefix(BACKUP_PREFIX)?
+            .strip_suffix(BACKUP_SUFFIX)
+            .and_then(|ts| NaiveDateTime::parse_from_str(ts, BACKUP_TIME_FORMAT).ok())?;
+        Some(Backup { path, timestamp })
+    }
+}
+
+/// Backups in `dir`, newest first.
+pub(crate) fn list(dir: &Path) -> Result<Vec<Backup>, Error> {
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        if let Some(backup) = Backup::from_path(entry?.path()) {
+            backups.push(backup);
+        }
+    }
+    backups.sort_by_key(|b| std::cmp::Reverse(b.timestamp));
+    Ok(backups)
+}
+
+/// The policy stored at `path`, `None` if there is none.
+pub(crate) fn read_policy(path: &Path) -> Result<Option<RetentionPolicy>, Error> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map(Some).map_err(|e| {
+            Error::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.to_string_lossy()),
+            ))
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Stores `policy` at `path`, or removes the stored one.
+pub(crate) fn write_policy(path: &Path, policy: Option<&RetentionPolicy>) -> Result<(), Error> {
+    match policy {
+        Some(policy) => {
+            let contents =
+                serde_json::to_string(policy).expect("RetentionPolicy is json-serializable");
+            fs::write(path, contents)?;
+        }
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        },
+    }
+    Ok(())
+}
+
+/// Deletes the backups in `dir` that `policy` does not keep; returns them.
+pub(crate) fn prune(dir: &Path, policy: &RetentionPolicy) -> Result<Vec<Backup>, Error> {
+    let (_, pruned) = policy.apply(list(dir)?);
+    for backup in &pruned {
+        fs::remove_file(&backup.path)?;
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn backup(timestamp: &str) -> Backup {
+        Backup::from_path(PathBuf::from(format!(
+            "{BACKUP_PREFIX}{timestamp}{BACKUP_SUFFIX}"
+        )))
+        .expect("valid backup name")
+    }
+
+    #[test]
+    fn retention_keeps_last_daily_and_weekly() {
+        let backups = vec![
+            backup("20240320_120000"),
+            backup("20240320_110000"),
+            backup("20240320_100000"),
+            backup("20240319_100000"),
+            backup("20240319_090000"),
+            backup("20240312_100000"),
+            backup("20240301_100000"),
+        ];
+        let policy = RetentionPolicy {
+            keep_last: 1,
+            keep_daily: 2,
+            keep_weekly: 2,
+        };
+        let (keep, prune) = policy.apply(backups);
+        let names = |backups: &[Backup]| {
+            backups
+                .iter()
+                .map(|b| b.timestamp.format(BACKUP_TIME_FORMAT).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&keep),
+            ["20240320_120000", "20240319_100000", "20240312_100000"]
+        );
+        assert_eq!(
+            names(&prune),
+            [
+                "20240320_110000",
+                "20240320_100000",
+                "20240319_090000",
+                "20240301_100000"
+            ]
+        );
+    }
+
+    #[test]
+    fn unrelated_files_are_not_backups() {
+        assert!(Backup::from_path(PathBuf::from("keys.db")).is_none());
+        assert!(Backup::from_path(PathBuf::from("keys.backup.latest.db")).is_none());
+    }
+}
@@ -0,0 +1,404 @@
+//! Whether stored passwords appear in a local copy of the Have I Been Pwned
+//! list of breached passwords.
+//!
+//! Only SHA-1 hashes are looked up, and only the first 5 hex digits of a
+//! hash leave the process (as the name of a range to fetch), so neither the
+//! passwords nor their full hashes are ever sent anywhere. The list may be
+//!
+//! - a directory of range files, as written by the HIBP downloader: one
+//!   file per prefix, named `ABCDE` or `ABCDE.txt`, with `SUFFIX:COUNT`
+//!   lines;
+//! - a single file of `HASH:COUNT` lines ordered by hash, searched in place;
+//! - an `http://` endpoint serving the range files as `<url>/ABCDE`, like
+//!   a mirror of `api.pwnedpasswords.com/range`.
+
+use crate::{DbRecord, Error, FieldKind};
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Hex digits of the hash that name its range.
+const PREFIX_LENGTH: usize = 5;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HashList {
+    Directory(PathBuf),
+    SortedFile(PathBuf),
+    /// Base URL of the ranges
+    Http(String),
+}
+
+/// An `http://` URL, or the path of a directory or of a file.
+impl FromStr for HashList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("expected a directory, a file or an http:// URL".to_string());
+        }
+        if s.starts_with("https://") {
+            return Err("only http:// endpoints are supported; use a local mirror".to_string());
+        }
+        if s.starts_with("http://") {
+            return Ok(HashList::Http(s.trim_end_matches('/').to_string()));
+        }
+        let path = PathBuf::from(s);
+        if path.is_dir() {
+            Ok(HashList::Directory(path))
+        } else {
+            Ok(HashList::SortedFile(path))
+        }
+    }
+}
+
+impl fmt::Display for HashList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashList::Directory(path) | HashList::SortedFile(path) => {
+                write!(f, "{}", path.to_string_lossy())
+            }
+            HashList::Http(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// Hashes of the passwords in one range, with their record keys and field
+/// names.
+type Range<'a> = Vec<(Zeroizing<String>, &'a str, &'a str)>;
+
+/// A password found in the list.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Compromised {
+    pub key: String,
+    pub field: String,
+    /// How many times the list has seen the password
+    pub count: u64,
+}
+
+/// Looks up the passwords (fields of kind [`FieldKind::Password`]) of all
+/// records in `list`. Returns how many passwords were checked and the ones
+/// found, most frequently breached first.
+pub fn check(
+    data: &BTreeMap<String, DbRecord>,
+    list: &HashList,
+) -> Result<(usize, Vec<Compromised>), Error> {
+    // Hashes by range, so that each range is read once.
+    let mut by_prefix: BTreeMap<String, Range> = BTreeMap::new();
+    let mut checked = 0;
+    for record in data.values() {
+        for (name, value) in &record.value {
+            if value.is_empty() || record.field_meta(name).kind != FieldKind::Password {
+                continue;
+            }
+            let hash = sha1_hex(value);
+            by_prefix
+                .entry(hash[..PREFIX_LENGTH].to_string())
+                .or_default()
+                .push((hash, &record.key, name));
+            checked += 1;
+        }
+    }
+    let mut compromised = Vec::new();
+    for (prefix, hashes) in by_prefix {
+        // Suffixes and counts of the range; for a sorted file, just of the
+        // hashes looked for.
+        let counts = match list {
+            HashList::Directory(dir) => parse_range(&read_range_file(dir, &prefix)?)?,
+            HashList::Http(url) => parse_range(&http_get(&format!("{url}/{prefix}"))?)?,
+            HashList::SortedFile(path) => {
+                let mut counts = BTreeMap::new();
+                for (hash, _, _) in &hashes {
+                    if let Some(count) = find_in_sorted_file(path, hash)? {
+                        counts.insert(hash[PREFIX_LENGTH..].to_string(), count);
+                    }
+                }
+                counts
+            }
+        };
+        for (hash, key, field) in hashes {
+            let count = counts.get(&hash[PREFIX_LENGTH..]).copied();
+            if let Some(count) = count.filter(|count| *count > 0) {
+                compromised.push(Compromised {
+                    key: key.to_string(),
+                    field: field.to_string(),
+                    count,
+                });
+            }
+        }
+    }
+    compromised.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+    Ok((checked, compromised))
+}
+
+fn sha1_hex(password: &str) -> Zeroizing<String> {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let digest = Sha1::digest(password.as_bytes());
+    let mut hex = Zeroizing::new(String::with_capacity(40));
+    for byte in digest {
+        hex.push(char::from(DIGITS[usize::from(byte >> 4)]));
+        hex.push(char::from(DIGITS[usize::from(byte & 0xf)]));
+    }
+    hex
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Splits a `HASH:COUNT` or `SUFFIX:COUNT` line; the hash is upper-cased.
+fn parse_line(line: &str) -> Result<(String, u64), Error> {
+    let line = line.trim();
+    let Some((hash, count)) = line.split_once(':') else {
+        return Err(invalid_data(format!("invalid hash list line \"{line}\"")));
+    };
+    let count = count
+        .parse()
+        .map_err(|e| invalid_data(format!("invalid count in \"{line}\": {e}")))?;
+    Ok((hash.to_ascii_uppercase(), count))
+}
+
+fn parse_range(contents: &str) -> Result<BTreeMap<String, u64>, Error> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_line)
+        .collect()
+}
+
+fn read_range_file(dir: &Path, prefix: &str) -> Result<String, Error> {
+    for name in [format!("{prefix}.txt"), prefix.to_string()] {
+        match std::fs::read_to_string(dir.join(name)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            result => return Ok(result?),
+        }
+    }
+    Err(Error::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no range file {prefix} in {}", dir.to_string_lossy()),
+    )))
+}
+
+/// Binary search over the byte offsets of a file of lines ordered by hash.
+fn find_in_sorted_file(path: &Path, hash: &str) -> Result<Option<u64>, Error> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut lo = 0;
+    let mut hi = file.get_ref().metadata()?.len();
+    // `lo` is always the start of a line; the first line with a hash not
+    // less than `hash` starts in `lo..=hi`.
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let (start, line) = line_from(&mut file, mid)?;
+        if start >= hi || line.is_empty() {
+            hi = mid;
+            continue;
+        }
+        let (line_hash, _) = parse_line(&line)?;
+        if line_hash.as_str() < hash {
+            lo = start + line.len() as u64;
+        } else {
+            hi = start;
+        }
+    }
+    let (_, line) = line_from(&mut file, lo)?;
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    let (line_hash, count) = parse_line(&line)?;
+    Ok((line_hash == hash).then_some(count))
+}
+
+/// The first line that starts at or after `offset`, with its newline, and
+/// where it starts; an empty line at the end of the file.
+fn line_from(file: &mut BufReader<File>, offset: u64) -> Result<(u64, String), Error> {
+    let mut start = offset;
+    if offset > 0 {
+        file.seek(SeekFrom::Start(offset - 1))?;
+        let mut skipped = Vec::new();
+        file.read_until(b'\n', &mut skipped)?;
+        start = offset - 1 + skipped.len() as u64;
+    } else {
+        file.seek(SeekFrom::Start(0))?;
+    }
+    let mut line = String::new();
+    file.read_line(&mut line)?;
+    Ok((start, line))
+}
+
+/// A plain HTTP/1.0 GET, for endpoints on the local network.
+fn http_get(url: &str) -> Result<String, Error> {
+    let rest = url.strip_prefix("http://").unwrap_or(url);
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+    let path = if path.is_empty() { "/" } else { path };
+    write!(
+        stream,
+        "GET {path} HTTP/1.0\r\nHost: {host}\r\nUser-Agent: cred-man\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| invalid_data(format!("invalid HTTP response from {url}")))?;
+    let mut lines = head.lines();
+    let status = lines.next().unwrap_or_default();
+    let mut words = status.split_whitespace();
+    if !words
+        .next()
+        .is_some_and(|version| version.starts_with("HTTP/1."))
+        || words.next() != Some("200")
+    {
+        return Err(Error::Io(io::Error::other(format!("{url}: {status}"))));
+    }
+    // The body is taken as it is, which a chunked (or otherwise encoded)
+    // one is not.
+    if lines.any(|line| {
+        line.split_once(':')
+            .is_some_and(|(name, _)| name.trim().eq_ignore_ascii_case("transfer-encoding"))
+    }) {
+        return Err(invalid_data(format!(
+            "{url}: unsupported Transfer-Encoding in response"
+        )));
+    }
+    Ok(body.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    const BREACHED: [(&str, u64); 3] =
+        [("password1", 2_418_984), ("letmein", 500), ("hunter2", 17)];
+
+    fn data() -> BTreeMap<String, DbRecord> {
+        let mut data = BTreeMap::new();
+        for (key, password) in [
+            ("a", "letmein"),
+            ("b", "correct-horse-battery-staple"),
+            ("c", "password1"),
+        ] {
+            let mut record = DbRecord::new(key.to_string());
+            record
+                .value
+                .insert("password".to_string(), password.to_string());
+            record
+                .value
+                .insert("note".to_string(), "hunter2".to_string());
+            data.insert(key.to_string(), record);
+        }
+        data
+    }
+
+    fn expected() -> Vec<Compromised> {
+        vec![
+            Compromised {
+                key: "c".to_string(),
+                field: "password".to_string(),
+                count: 2_418_984,
+            },
+            Compromised {
+                key: "a".to_string(),
+                field: "password".to_string(),
+                count: 500,
+            },
+        ]
+    }
+
+    /// A sorted file of `HASH:COUNT` lines with the breached passwords and
+    /// some filler, and the range files of the passwords in [`data`].
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testdata/breach")
+    }
+
+    #[test]
+    fn finds_passwords_in_files_and_directories() {
+        for source in [fixture().join("sorted.txt"), fixture().join("ranges")] {
+            let list = HashList::from_str(&source.to_string_lossy()).expect("valid");
+            assert_eq!(check(&data(), &list).expect("checked"), (3, expected()));
+        }
+        // A range missing from the mirror.
+        let mut data = data();
+        data.get_mut("b")
+            .expect("record")
+            .value
+            .insert("password".to_string(), "not-in-the-mirror".to_string());
+        assert!(check(&data, &HashList::Directory(fixture().join("ranges"))).is_err());
+    }
+
+    #[test]
+    fn sends_only_hash_prefixes() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let url = format!("http://{}/range", listener.local_addr().expect("address"));
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming().take(3) {
+                let mut stream = stream.expect("connection");
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                while !request.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut request).expect("request");
+                }
+                let prefix =
+                    request.split('/').nth(2).expect("prefix")[..PREFIX_LENGTH].to_string();
+                let range = read_range_file(&fixture().join("ranges"), &prefix).expect("range");
+                write!(stream, "HTTP/1.1 200 OK\r\n\r\n{range}").expect("response");
+                requests.push(request);
+            }
+            requests
+        });
+
+        let list = HashList::from_str(&url).expect("valid");
+        assert_eq!(check(&data(), &list).expect("checked"), (3, expected()));
+        for request in server.join().expect("server") {
+            let request_line = request.lines().next().expect("request line");
+            assert!(request_line.starts_with("GET /range/"), "{request}");
+            assert_eq!(request_line.len(), "GET /range/ABCDE HTTP/1.0".len());
+            for (password, _) in BREACHED {
+                assert!(!request.contains(&sha1_hex(password)[PREFIX_LENGTH..]));
+                assert!(!request.contains(password));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_other_responses() {
+        const RESPONSES: [&str; 3] = [
+            "HTTP/1.1 404 Not Found\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nABCDE\r\n0\r\n\r\n",
+            "SSH-2.0-OpenSSH 200\r\n\r\n",
+        ];
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let url = format!("http://{}/range", listener.local_addr().expect("address"));
+        let server = std::thread::spawn(move || {
+            for (response, stream) in RESPONSES.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.expect("connection");
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                while !request.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut request).expect("request");
+                }
+                stream.write_all(response.as_bytes()).expect("response");
+            }
+        });
+        for _ in RESPONSES {
+            assert!(http_get(&format!("{url}/ABCDE")).is_err());
+        }
+        server.join().expect("server");
+    }
+}
@@ -1,9 +1,13 @@
+use crate::secret::{Secret, SecretBytes, SecretString};
+use crate::Error;
 use getrandom::getrandom;
+use sha2::{Digest, Sha256};
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
 use std::path::Path;
 use std::str;
+use zeroize::{Zeroize, Zeroizing};
 
 #[must_use]
 pub fn generate_salt(n: usize) -> Vec<u8> {
@@ -12,71 +16,476 @@ pub fn generate_salt(n: usize) -> Vec<u8> {
     data
 }
 
+/// Key derivation function together with its cost parameters.
+///
+/// Starting with file format version 2 this is stored in the file header,
+/// so the work factor can be raised without breaking existing files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KdfParams {
+    Scrypt {
+        log_n: u8,
+        r: u32,
+        p: u32,
+    },
+    /// Argon2id; `m_cost` is in KiB.
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+}
+
+/// Key derivation function without its parameters, used to select the
+/// function for new (or re-encrypted) databases.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KdfAlgorithm {
+    Scrypt,
+    Argon2id,
+}
+
+impl KdfAlgorithm {
+    #[must_use]
+    pub fn recommended_params(self) -> KdfParams {
+        match self {
+            KdfAlgorithm::Scrypt => KdfParams::Scrypt {
+                log_n: 15,
+                r: 8,
+                p: 1,
+            },
+            KdfAlgorithm::Argon2id => KdfParams::Argon2id {
+                m_cost: 64 * 1024,
+                t_cost: 3,
+                p_cost: 1,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for KdfAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KdfAlgorithm::Scrypt => write!(f, "scrypt"),
+            KdfAlgorithm::Argon2id => write!(f, "argon2id"),
+        }
+    }
+}
+
+impl std::str::FromStr for KdfAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scrypt" => Ok(KdfAlgorithm::Scrypt),
+            "argon2id" => Ok(KdfAlgorithm::Argon2id),
+            _ => Err(format!(
+                "unknown key derivation function \"{s}\"; expected scrypt or argon2id"
+            )),
+        }
+    }
+}
+
+const KDF_ID_SCRYPT: i32 = 1;
+const KDF_ID_ARGON2ID: i32 = 2;
+
+/// Largest KDF parameters accepted, so that a damaged or crafted header can
+/// not make deriving the key take all memory or run for hours. Far above
+/// [`KdfAlgorithm::recommended_params`].
+pub const MAX_SCRYPT_LOG_N: u8 = 22;
+pub const MAX_SCRYPT_R: u32 = 32;
+pub const MAX_SCRYPT_P: u32 = 16;
+/// 4 GiB, in KiB; also the limit of the memory scrypt uses (`128 * r * n`
+/// bytes).
+pub const MAX_ARGON2_M_COST: u32 = 4 * 1024 * 1024;
+pub const MAX_ARGON2_T_COST: u32 = 64;
+pub const MAX_ARGON2_P_COST: u32 = 16;
+
+impl KdfParams {
+    /// Parameters used by version 1 files, which did not record them.
+    pub const LEGACY: KdfParams = KdfParams::Scrypt {
+        log_n: 14,
+        r: 8,
+        p: 1,
+    };
+
+    /// Parameters used when creating new databases.
+    #[must_use]
+    pub fn recommended() -> KdfParams {
+        KdfAlgorithm::Argon2id.recommended_params()
+    }
+
+    #[must_use]
+    pub fn algorithm(&self) -> KdfAlgorithm {
+        match self {
+            KdfParams::Scrypt { .. } => KdfAlgorithm::Scrypt,
+            KdfParams::Argon2id { .. } => KdfAlgorithm::Argon2id,
+        }
+    }
+
+    fn id(&self) -> i32 {
+        match self {
+            KdfParams::Scrypt { .. } => KDF_ID_SCRYPT,
+            KdfParams::Argon2id { .. } => KDF_ID_ARGON2ID,
+        }
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn to_values(self) -> Vec<i32> {
+        match self {
+            KdfParams::Scrypt { log_n, r, p } => vec![i32::from(log_n), r as i32, p as i32],
+            KdfParams::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => vec![m_cost as i32, t_cost as i32, p_cost as i32],
+        }
+    }
+
+    fn from_values(id: i32, values: &[i32]) -> Result<KdfParams, Error> {
+        let params = match (id, values) {
+            (KDF_ID_SCRYPT, &[log_n, r, p]) => KdfParams::Scrypt {
+                log_n: u8::try_from(log_n).map_err(|_| invalid_kdf_params())?,
+                r: u32::try_from(r).map_err(|_| invalid_kdf_params())?,
+                p: u32::try_from(p).map_err(|_| invalid_kdf_params())?,
+            },
+            (KDF_ID_ARGON2ID, &[m_cost, t_cost, p_cost]) => KdfParams::Argon2id {
+                m_cost: u32::try_from(m_cost).map_err(|_| invalid_kdf_params())?,
+                t_cost: u32::try_from(t_cost).map_err(|_| invalid_kdf_params())?,
+                p_cost: u32::try_from(p_cost).map_err(|_| invalid_kdf_params())?,
+            },
+            (KDF_ID_SCRYPT | KDF_ID_ARGON2ID, _) => return Err(invalid_kdf_params()),
+            (id, _) => {
+                return Err(Error::UnsupportedFormat(format!(
+                    "unknown key derivation function {id}"
+                )))
+            }
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    /// Checks that the parameters are usable and within the `MAX_*` limits.
+    pub fn validate(self) -> Result<(), Error> {
+        match self {
+            KdfParams::Scrypt { log_n, r, p } => {
+                let memory_kib = (u64::from(r) * 128) << log_n.min(MAX_SCRYPT_LOG_N) >> 10;
+                if log_n > MAX_SCRYPT_LOG_N
+                    || r > MAX_SCRYPT_R
+                    || p > MAX_SCRYPT_P
+                    || memory_kib > u64::from(MAX_ARGON2_M_COST)
+                {
+                    return Err(invalid_kdf_params());
+                }
+                scrypt::Params::new(log_n, r, p, 32).map_err(|_| invalid_kdf_params())?;
+            }
+            KdfParams::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => {
+                if m_cost > MAX_ARGON2_M_COST
+                    || t_cost > MAX_ARGON2_T_COST
+                    || p_cost > MAX_ARGON2_P_COST
+                {
+                    return Err(invalid_kdf_params());
+                }
+                argon2::Params::new(m_cost, t_cost, p_cost, Some(32))
+                    .map_err(|_| invalid_kdf_params())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid_kdf_params() -> Error {
+    Error::UnsupportedFormat("invalid key derivation parameters".to_string())
+}
+
+/// Secret material the AES key is derived from: the master password and,
+/// optionally, the contents of a key file.
+#[derive(Clone)]
+pub struct CompositeKey {
+    password: SecretString,
+    /// SHA-256 of the key file contents
+    key_file_hash: Option<SecretBytes>,
+}
+
+impl CompositeKey {
+    pub fn new(password: &str, key_file: Option<&Path>) -> io::Result<CompositeKey> {
+        let key_file_hash = match key_file {
+            Some(path) => {
+                let contents = Zeroizing::new(std::fs::read(path)?);
+                Some(Secret::new(Sha256::digest(&*contents).to_vec()))
+            }
+            None => None,
+        };
+        Ok(CompositeKey {
+            password: Secret::new(password.to_owned()),
+            key_file_hash,
+        })
+    }
+
+    #[must_use]
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    #[must_use]
+    pub fn has_key_file(&self) -> bool {
+        self.key_file_hash.is_some()
+    }
+
+    /// Same key file, different password.
+    #[must_use]
+    pub fn with_password(&self, password: &str) -> CompositeKey {
+        CompositeKey {
+            password: Secret::new(password.to_owned()),
+            key_file_hash: self.key_file_hash.clone(),
+        }
+    }
+
+    fn kdf_input(&self) -> SecretBytes {
+        match &self.key_file_hash {
+            // Password-only keys are passed to the KDF as is, which keeps
+            // files written before key file support readable.
+            None => Secret::new(self.password.as_bytes().to_vec()),
+            Some(key_file_hash) => {
+                let mut input = Vec::with_capacity(64);
+                input.extend_from_slice(&Sha256::digest(self.password.as_bytes()));
+                input.extend_from_slice(key_file_hash);
+                Secret::new(input)
+            }
+        }
+    }
+}
+
 #[must_use]
-pub fn derive_key(salt: &[u8], password: &str) -> Vec<u8> {
-    use scrypt::Params;
+pub fn derive_key(salt: &[u8], key: &CompositeKey, kdf: &KdfParams) -> SecretBytes {
+    let password = key.kdf_input();
     let mut result = vec![0u8; 32];
-    scrypt::scrypt(
-        password.as_bytes(),
-        salt,
-        &Params::new(14, 8, 1, 32).expect("parameters are hardcoded and valid"),
-        &mut result,
-    )
-    .expect("result.len == 32");
-    result
+    match *kdf {
+        KdfParams::Scrypt { log_n, r, p } => {
+            scrypt::scrypt(
+                &password,
+                salt,
+                &scrypt::Params::new(log_n, r, p, 32).expect("parameters are validated"),
+                &mut result,
+            )
+            .expect("result.len == 32");
+        }
+        KdfParams::Argon2id {
+            m_cost,
+            t_cost,
+            p_cost,
+        } => {
+            argon2::Argon2::new(
+                argon2::Algorithm::Argon2id,
+                argon2::Version::V0x13,
+                argon2::Params::new(m_cost, t_cost, p_cost, Some(32))
+                    .expect("parameters are validated"),
+            )
+            .hash_password_into(&password, salt, &mut result)
+            .expect("salt and result lengths are valid");
+        }
+    }
+    Secret::new(result)
+}
+
+/// AES key derived from a [`CompositeKey`] with a particular salt and
+/// KDF parameters.
+///
+/// Deriving it is slow on purpose, so it is done once on unlock and then
+/// reused for every save; only the nonce changes between saves.
+#[derive(Clone)]
+pub struct DerivedKey {
+    kdf: KdfParams,
+    salt: Vec<u8>,
+    requires_key_file: bool,
+    aes_key: SecretBytes,
+}
+
+impl DerivedKey {
+    /// Derives a key with a fresh random salt. Fails if `kdf` is not
+    /// valid (see [`KdfParams::validate`]), so that no file is written that
+    /// could not be read back.
+    pub fn new(key: &CompositeKey, kdf: KdfParams) -> Result<DerivedKey, Error> {
+        kdf.validate()?;
+        Ok(DerivedKey::with_salt(key, kdf, generate_salt(SALT_LEN)))
+    }
+
+    /// Derives the key `data` was encrypted with, i.e. with its salt and
+    /// KDF parameters. Fails early, without running the KDF, if the key file
+    /// presence does not match the header.
+    pub fn for_file(data: &EncryptedFileContent, key: &CompositeKey) -> Result<DerivedKey, Error> {
+        if data.requires_key_file && !key.has_key_file() {
+            return Err(Error::KeyFileRequired);
+        }
+        if data.requires_key_file != key.has_key_file() {
+            return Err(Error::WrongPassword);
+        }
+        Ok(DerivedKey::with_salt(key, data.kdf, data.salt.clone()))
+    }
+
+    fn with_salt(key: &CompositeKey, kdf: KdfParams, salt: Vec<u8>) -> DerivedKey {
+        DerivedKey {
+            aes_key: derive_key(&salt, key, &kdf),
+            kdf,
+            salt,
+            requires_key_file: key.has_key_file(),
+        }
+    }
+
+    #[must_use]
+    pub fn kdf(&self) -> KdfParams {
+        self.kdf
+    }
+
+    /// Whether `data` was encrypted with this salt and KDF parameters, i.e.
+    /// whether this key can decrypt it (given the right password).
+    #[must_use]
+    pub fn is_for(&self, data: &EncryptedFileContent) -> bool {
+        data.requires_key_file == self.requires_key_file
+            && data.kdf == self.kdf
+            && data.salt == self.salt
+    }
 }
 
 #[allow(clippy::module_name_repetitions)]
 pub struct EncryptedFileContent {
+    version: i32,
+    requires_key_file: bool,
+    kdf: KdfParams,
     salt: Vec<u8>,
     nonce: Vec<u8>,
+    /// Lets a wrong password be told apart from a damaged file (version 3+)
+    key_check: Vec<u8>,
     tag: Vec<u8>,
     ciphertext: Vec<u8>,
 }
 
-pub fn encrypt(plaintext: &str, password: &str) -> EncryptedFileContent {
+impl EncryptedFileContent {
+    #[must_use]
+    pub fn kdf(&self) -> KdfParams {
+        self.kdf
+    }
+
+    #[must_use]
+    pub fn requires_key_file(&self) -> bool {
+        self.requires_key_file
+    }
+
+    /// Serialized file header: everything that precedes the tag and the
+    /// ciphertext. Starting with version 3 it is authenticated as AAD.
+    fn header_bytes(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(CRED_MAN_MAGIC);
+        header.extend_from_slice(&i32_to_bytes(self.version));
+        if self.version >= 2 {
+            let flags = if self.requires_key_file {
+                FLAG_KEY_FILE
+            } else {
+                0
+            };
+            header.extend_from_slice(&i32_to_bytes(flags));
+            let kdf_values = self.kdf.to_values();
+            header.extend_from_slice(&i32_to_bytes(self.kdf.id()));
+            #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+            header.extend_from_slice(&i32_to_bytes(kdf_values.len() as i32));
+            for value in kdf_values {
+                header.extend_from_slice(&i32_to_bytes(value));
+            }
+        }
+        header.extend_from_slice(&self.salt);
+        header.extend_from_slice(&self.nonce);
+        if self.version >= 3 {
+            header.extend_from_slice(&self.key_check);
+        }
+        header
+    }
+
+    fn aad(&self) -> Vec<u8> {
+        if self.version >= 3 {
+            self.header_bytes()
+        } else {
+            LEGACY_AAD.to_vec()
+        }
+    }
+}
+
+/// AAD used by versions 1 and 2, which did not authenticate the header.
+const LEGACY_AAD: &[u8] = b"cred-man";
+
+const KEY_CHECK_LEN: usize = 16;
+
+fn key_check(aes_key: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"cred-man key check");
+    hasher.update(aes_key);
+    hasher.finalize()[..KEY_CHECK_LEN].to_vec()
+}
+
+pub fn encrypt(
+    plaintext: &str,
+    key: &CompositeKey,
+    kdf: KdfParams,
+) -> Result<EncryptedFileContent, Error> {
+    Ok(encrypt_with_key(plaintext, &DerivedKey::new(key, kdf)?))
+}
+
+/// Encrypts with an already derived key and a fresh nonce.
+#[must_use]
+pub fn encrypt_with_key(plaintext: &str, key: &DerivedKey) -> EncryptedFileContent {
+    encrypt_as_version(CRED_MAN_VERSION, plaintext, key)
+}
+
+fn encrypt_as_version(version: i32, plaintext: &str, key: &DerivedKey) -> EncryptedFileContent {
     use aes_gcm::{
         aead::{Aead, OsRng, Payload},
         AeadCore, Aes256Gcm, Key, KeyInit,
     };
-    let salt = generate_salt(16);
-    let key = derive_key(&salt, password);
-    let key = Key::<Aes256Gcm>::from_slice(&key);
-    let cipher = Aes256Gcm::new(key);
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-    // let mut tag = vec![0u8; 16];
-    let aad = b"cred-man";
+    let mut data = EncryptedFileContent {
+        version,
+        requires_key_file: key.requires_key_file,
+        kdf: key.kdf,
+        salt: key.salt.clone(),
+        nonce: nonce.to_vec(),
+        key_check: key_check(&key.aes_key),
+        tag: Vec::new(),
+        ciphertext: Vec::new(),
+    };
+    let aes_key = Key::<Aes256Gcm>::from_slice(&key.aes_key);
+    let cipher = Aes256Gcm::new(aes_key);
     let result = cipher
         .encrypt(
             &nonce,
             Payload {
                 msg: plaintext.as_bytes(),
-                aad,
+                aad: &data.aad(),
             },
         )
         .expect("should not be fallible");
-    let tag = result[(result.len() - 16)..].to_vec();
-    let ciphertext = result[..(result.len() - 16)].to_vec();
-
-    EncryptedFileContent {
-        salt,
-        nonce: nonce.to_vec(),
-        tag,
-        ciphertext,
-    }
+    data.tag = result[(result.len() - 16)..].to_vec();
+    data.ciphertext = result[..(result.len() - 16)].to_vec();
+    data
 }
 
-fn read_bytes(source: &mut dyn Read, buffer: &mut [u8]) -> io::Result<()> {
-    let mut pos = 0;
-    while pos < buffer.len() {
-        pos += source.read(buffer.split_at_mut(pos).1)?;
-    }
-    Ok(())
+fn read_bytes(source: &mut dyn Read, buffer: &mut [u8]) -> Result<(), Error> {
+    source.read_exact(buffer).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Truncated,
+        _ => Error::Io(e),
+    })
 }
 
 const CRED_MAN_MAGIC: &[u8] = b"CREDMAN";
 
-const CRED_MAN_VERSION: i32 = 1;
+const CRED_MAN_VERSION: i32 = 3;
+
+const SALT_LEN: usize = 16;
+
+const MAX_KDF_VALUES: usize = 16;
+
+/// Header flag: the key is derived from the password and a key file.
+const FLAG_KEY_FILE: i32 = 1;
 
 #[must_use]
 #[allow(clippy::cast_sign_loss)]
@@ -90,88 +499,375 @@ pub fn i32_to_bytes(x: i32) -> [u8; 4] {
     v
 }
 
+#[must_use]
+#[allow(clippy::cast_lossless)]
+pub fn i32_from_bytes(x: [u8; 4]) -> i32 {
+    ((x[0] as i32) << 24) | ((x[1] as i32) << 16) | ((x[2] as i32) << 8) | (x[3] as i32)
+}
+
+fn read_i32(source: &mut dyn Read) -> Result<i32, Error> {
+    let mut bytes = [0u8; 4];
+    read_bytes(source, &mut bytes)?;
+    Ok(i32_from_bytes(bytes))
+}
+
 pub fn write_to_file<P: AsRef<Path>>(file_name: P, data: &EncryptedFileContent) -> io::Result<()> {
     let mut file = File::create(file_name)?;
 
-    file.write_all(CRED_MAN_MAGIC)?;
-    file.write_all(&i32_to_bytes(CRED_MAN_VERSION))?;
-    file.write_all(&data.salt)?;
-    file.write_all(&data.nonce)?;
+    file.write_all(&data.header_bytes())?;
     file.write_all(&data.tag)?;
     file.write_all(&data.ciphertext)?;
 
     Ok(())
 }
 
-pub fn parse_file<P: AsRef<Path>>(file_name: P) -> io::Result<EncryptedFileContent> {
+pub fn parse_file<P: AsRef<Path>>(file_name: P) -> Result<EncryptedFileContent, Error> {
     let mut file = File::open(file_name)?;
-    #[allow(clippy::cast_possible_truncation)]
-    let size = file.metadata()?.len() as usize;
+    let size = usize::try_from(file.metadata()?.len()).map_err(|_| Error::NotADatabase)?;
+    parse(&mut file, size)
+}
+
+/// Same as [`parse_file`], for a file that is already in memory.
+pub fn parse_bytes(bytes: &[u8]) -> Result<EncryptedFileContent, Error> {
+    parse(&mut &bytes[..], bytes.len())
+}
 
+/// Parses `size` bytes of `source`. Nothing is allocated based on the
+/// contents until the magic and the header are known to be valid.
+fn parse(source: &mut dyn Read, size: usize) -> Result<EncryptedFileContent, Error> {
     let mut magic = vec![0u8; CRED_MAN_MAGIC.len()];
-    let mut ver_bytes = [0u8; 4];
-    let mut salt = vec![0u8; 16];
+    read_bytes(source, &mut magic)?;
+    if magic != CRED_MAN_MAGIC {
+        return Err(Error::NotADatabase);
+    }
+
+    let ver = read_i32(source)?;
+    let mut header_len = CRED_MAN_MAGIC.len() + 4;
+    let (requires_key_file, kdf) = match ver {
+        1 => (false, KdfParams::LEGACY),
+        2 | 3 => {
+            let flags = read_i32(source)?;
+            if flags & !FLAG_KEY_FILE != 0 {
+                return Err(Error::UnsupportedFormat(format!(
+                    "unknown flags {flags:#x}"
+                )));
+            }
+            let kdf_id = read_i32(source)?;
+            let kdf_values_count = usize::try_from(read_i32(source)?)
+                .ok()
+                .filter(|&count| count <= MAX_KDF_VALUES)
+                .ok_or_else(invalid_kdf_params)?;
+            let mut kdf_values = Vec::with_capacity(kdf_values_count);
+            for _ in 0..kdf_values_count {
+                kdf_values.push(read_i32(source)?);
+            }
+            header_len += 12 + 4 * kdf_values_count;
+            (
+                flags & FLAG_KEY_FILE != 0,
+                KdfParams::from_values(kdf_id, &kdf_values)?,
+            )
+        }
+        _ => {
+            return Err(Error::UnsupportedVersion(ver));
+        }
+    };
+    let key_check_len = if ver >= 3 { KEY_CHECK_LEN } else { 0 };
+
+    let mut salt = vec![0u8; SALT_LEN];
     let mut nonce = vec![0u8; 12];
+    let mut key_check = vec![0u8; key_check_len];
     let mut tag = vec![0u8; 16];
-    let mut ciphertext = vec![0u8; size - CRED_MAN_MAGIC.len() - 4 - 16 - 12 - 16];
+    let ciphertext_len = size
+        .checked_sub(header_len + SALT_LEN + 12 + key_check_len + 16)
+        .ok_or(Error::Truncated)?;
+    let mut ciphertext = vec![0u8; ciphertext_len];
 
-    read_bytes(&mut file, &mut magic)?;
-    read_bytes(&mut file, &mut ver_bytes)?;
-    read_bytes(&mut file, &mut salt)?;
-    read_bytes(&mut file, &mut nonce)?;
-    read_bytes(&mut file, &mut tag)?;
-    read_bytes(&mut file, &mut ciphertext)?;
-
-    if magic != CRED_MAN_MAGIC {
-        return Err(io::Error::new(io::ErrorKind::Other, "MAGIC mismatch"));
-    }
-    #[allow(clippy::cast_lossless)]
-    let ver: i32 = ((ver_bytes[0] as i32) << 24)
-        | ((ver_bytes[1] as i32) << 16)
-        | ((ver_bytes[2] as i32) << 8)
-        | (ver_bytes[3] as i32);
-
-    if ver > 1 {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Unsupported credentials database version: {ver}"),
-        ));
-    }
+    read_bytes(source, &mut salt)?;
+    read_bytes(source, &mut nonce)?;
+    read_bytes(source, &mut key_check)?;
+    read_bytes(source, &mut tag)?;
+    read_bytes(source, &mut ciphertext)?;
 
     Ok(EncryptedFileContent {
+        version: ver,
+        requires_key_file,
+        kdf,
         salt,
         nonce,
+        key_check,
         tag,
         ciphertext,
     })
 }
 
-#[must_use]
-pub fn decrypt(data: &EncryptedFileContent, password: &str) -> Option<String> {
+/// Fails with [`Error::WrongPassword`] if the password (or key file) is
+/// wrong, and with [`Error::Tampered`] if the key is right but the file does
+/// not authenticate, i.e. the header or the ciphertext were modified.
+///
+/// The salt and the KDF parameters are inputs of the key, so modifying them
+/// yields a different key and [`Error::WrongPassword`], as does changing the
+/// version of a version 3 file to 2, which has no key check. Without the
+/// right key there is nothing to tell such files from a wrong password with.
+pub fn decrypt(data: &EncryptedFileContent, key: &CompositeKey) -> Result<SecretString, Error> {
+    decrypt_with_key(data, &DerivedKey::for_file(data, key)?)
+}
+
+/// Same as [`decrypt`], with a key obtained from [`DerivedKey::for_file`].
+pub fn decrypt_with_key(
+    data: &EncryptedFileContent,
+    key: &DerivedKey,
+) -> Result<SecretString, Error> {
     use aes_gcm::{
         aead::{Aead, Nonce, Payload},
         Aes256Gcm, Key, KeyInit,
     };
-    let key = derive_key(&data.salt, password);
-    let aad = b"cred-man";
+    if !key.is_for(data) {
+        return Err(Error::WrongPassword);
+    }
+    let aes_key = &key.aes_key;
+    let key_checked = data.version >= 3;
+    if key_checked && key_check(aes_key) != data.key_check {
+        return Err(Error::WrongPassword);
+    }
     let mut ciphertext = Vec::with_capacity(data.tag.len() + data.ciphertext.len());
     ciphertext.extend_from_slice(&data.ciphertext);
     ciphertext.extend_from_slice(&data.tag);
 
-    let key = Key::<Aes256Gcm>::from_slice(&key);
-    let cipher = Aes256Gcm::new(key);
+    let aes_key = Key::<Aes256Gcm>::from_slice(aes_key);
+    let cipher = Aes256Gcm::new(aes_key);
     let nonce = Nonce::<Aes256Gcm>::from_slice(&data.nonce);
-    let plaintext = cipher
-        .decrypt(
-            nonce,
-            Payload {
-                msg: &ciphertext,
-                aad,
-            },
+    let Ok(plaintext) = cipher.decrypt(
+        nonce,
+        Payload {
+            msg: &ciphertext,
+            aad: &data.aad(),
+        },
+    ) else {
+        return Err(if key_checked {
+            Error::Tampered
+        } else {
+            Error::WrongPassword
+        });
+    };
+
+    let plaintext = String::from_utf8(plaintext).map_err(|e| {
+        e.into_bytes().zeroize();
+        Error::Corrupt("invalid UTF-8".to_string())
+    })?;
+
+    Ok(Secret::new(plaintext))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEST_KDF: KdfParams = KdfParams::Scrypt {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("cred-man-test-{}-{name}", std::process::id()))
+    }
+
+    fn password(password: &str) -> CompositeKey {
+        CompositeKey::new(password, None).expect("no key file to read")
+    }
+
+    fn decrypted(data: &EncryptedFileContent, key: &CompositeKey) -> Option<String> {
+        match decrypt(data, key) {
+            Ok(plaintext) => Some(plaintext.to_string()),
+            Err(Error::WrongPassword) => None,
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn roundtrip_preserves_kdf_params() {
+        let path = temp_path("roundtrip.db");
+        let data = encrypt("[]", &password("secret"), TEST_KDF).expect("valid parameters");
+        write_to_file(&path, &data).expect("write should succeed");
+        let parsed = parse_file(&path).expect("parse should succeed");
+        std::fs::remove_file(&path).expect("remove should succeed");
+        assert_eq!(parsed.kdf(), TEST_KDF);
+        assert_eq!(
+            decrypted(&parsed, &password("secret")).as_deref(),
+            Some("[]")
+        );
+        assert_eq!(decrypted(&parsed, &password("wrong")), None);
+    }
+
+    #[test]
+    fn argon2id_roundtrip() {
+        let kdf = KdfParams::Argon2id {
+            m_cost: 64,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let path = temp_path("argon2id.db");
+        write_to_file(
+            &path,
+            &encrypt("[]", &password("secret"), kdf).expect("valid parameters"),
         )
-        .ok()?;
+        .expect("write should succeed");
+        let parsed = parse_file(&path).expect("parse should succeed");
+        std::fs::remove_file(&path).expect("remove should succeed");
+        assert_eq!(parsed.kdf(), kdf);
+        assert_eq!(
+            decrypted(&parsed, &password("secret")).as_deref(),
+            Some("[]")
+        );
+        assert_eq!(decrypted(&parsed, &password("wrong")), None);
+    }
+
+    #[test]
+    fn rejects_oversized_kdf_params() {
+        for (kdf_id, values) in [
+            (KDF_ID_SCRYPT, [30, 8, 1]),
+            (KDF_ID_SCRYPT, [22, 32, 1]),
+            (KDF_ID_SCRYPT, [15, 8, 1_000_000]),
+            (KDF_ID_ARGON2ID, [i32::MAX, 3, 1]),
+            (KDF_ID_ARGON2ID, [64 * 1024, 1_000_000, 1]),
+        ] {
+            let mut header = CRED_MAN_MAGIC.to_vec();
+            for value in [CRED_MAN_VERSION, 0, kdf_id, 3].into_iter().chain(values) {
+                header.extend_from_slice(&i32_to_bytes(value));
+            }
+            header.resize(header.len() + SALT_LEN + 12 + KEY_CHECK_LEN + 16, 0);
+            assert!(
+                matches!(parse_bytes(&header), Err(Error::UnsupportedFormat(_))),
+                "{values:?}"
+            );
+        }
+    }
 
-    let plaintext = String::from_utf8(plaintext).ok()?;
+    #[test]
+    fn refuses_to_encrypt_with_oversized_kdf_params() {
+        let kdf = KdfParams::Argon2id {
+            m_cost: MAX_ARGON2_M_COST + 1,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        assert!(matches!(
+            encrypt("[]", &password("secret"), kdf),
+            Err(Error::UnsupportedFormat(_))
+        ));
+    }
 
-    Some(plaintext)
+    #[test]
+    fn reads_older_versions() {
+        for (version, kdf) in [(1, KdfParams::LEGACY), (2, TEST_KDF)] {
+            let path = temp_path(&format!("v{version}.db"));
+            let key = DerivedKey::new(&password("secret"), kdf).expect("valid parameters");
+            let data = encrypt_as_version(version, "[]", &key);
+            write_to_file(&path, &data).expect("write should succeed");
+            let parsed = parse_file(&path).expect("parse should succeed");
+            std::fs::remove_file(&path).expect("remove should succeed");
+            assert_eq!(parsed.kdf(), kdf);
+            assert_eq!(
+                decrypted(&parsed, &password("secret")).as_deref(),
+                Some("[]")
+            );
+            assert_eq!(decrypted(&parsed, &password("wrong")), None);
+        }
+    }
+
+    #[test]
+    fn header_tampering_is_not_reported_as_wrong_password() {
+        let mut data = encrypt("[]", &password("secret"), TEST_KDF).expect("valid parameters");
+        data.nonce[0] ^= 1;
+        assert!(matches!(
+            decrypt(&data, &password("secret")),
+            Err(Error::Tampered)
+        ));
+        assert_eq!(decrypted(&data, &password("wrong")), None);
+    }
+
+    #[test]
+    fn key_derivation_tampering_is_reported_as_wrong_password() {
+        let data = || encrypt("[]", &password("secret"), TEST_KDF).expect("valid parameters");
+
+        let mut salt_changed = data();
+        salt_changed.salt[0] ^= 1;
+        assert_eq!(decrypted(&salt_changed, &password("secret")), None);
+
+        let mut kdf_changed = data();
+        kdf_changed.kdf = KdfParams::Scrypt {
+            log_n: 5,
+            r: 8,
+            p: 1,
+        };
+        assert_eq!(decrypted(&kdf_changed, &password("secret")), None);
+
+        let original = data();
+        let mut bytes = original.header_bytes();
+        bytes.extend_from_slice(&original.tag);
+        bytes.extend_from_slice(&original.ciphertext);
+        bytes[CRED_MAN_MAGIC.len()..CRED_MAN_MAGIC.len() + 4].copy_from_slice(&i32_to_bytes(2));
+        let downgraded = parse_bytes(&bytes).expect("parse should succeed");
+        assert_eq!(decrypted(&downgraded, &password("secret")), None);
+    }
+
+    #[test]
+    fn key_file_is_required_when_recorded_in_header() {
+        let path = temp_path("keyfile.db");
+        let key_file_path = temp_path("keyfile.key");
+        std::fs::write(&key_file_path, b"key file contents").expect("write should succeed");
+        let key = CompositeKey::new("secret", Some(&key_file_path)).expect("key file exists");
+        std::fs::remove_file(&key_file_path).expect("remove should succeed");
+
+        let data = encrypt("[]", &key, TEST_KDF).expect("valid parameters");
+        write_to_file(&path, &data).expect("write should succeed");
+        let parsed = parse_file(&path).expect("parse should succeed");
+        std::fs::remove_file(&path).expect("remove should succeed");
+        assert!(parsed.requires_key_file());
+        assert_eq!(decrypted(&parsed, &key).as_deref(), Some("[]"));
+        assert!(matches!(
+            decrypt(&parsed, &password("secret")),
+            Err(Error::KeyFileRequired)
+        ));
+        assert_eq!(decrypted(&parsed, &key.with_password("wrong")), None);
+    }
+
+    #[test]
+    fn derived_key_is_reused_with_fresh_nonce() {
+        let key = DerivedKey::new(&password("secret"), TEST_KDF).expect("valid parameters");
+        let first = encrypt_with_key("[]", &key);
+        let second = encrypt_with_key("[]", &key);
+        assert_eq!(first.salt, second.salt);
+        assert_ne!(first.nonce, second.nonce);
+
+        let file_key = DerivedKey::for_file(&second, &password("secret")).expect("no key file");
+        assert_eq!(
+            decrypt_with_key(&first, &file_key)
+                .expect("same salt and password")
+                .as_str(),
+            "[]"
+        );
+        let rotated = DerivedKey::new(&password("secret"), TEST_KDF).expect("valid parameters");
+        assert!(matches!(
+            decrypt_with_key(&first, &rotated),
+            Err(Error::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn truncated_files_are_reported() {
+        let data = encrypt("[]", &password("secret"), TEST_KDF).expect("valid parameters");
+        let mut bytes = data.header_bytes();
+        bytes.extend_from_slice(&data.tag);
+        for len in 0..bytes.len() {
+            assert!(
+                matches!(parse_bytes(&bytes[..len]), Err(Error::Truncated)),
+                "{len} bytes"
+            );
+        }
+        assert!(parse_bytes(&bytes).is_ok());
+        assert!(matches!(
+            parse_bytes(b"not a database"),
+            Err(Error::NotADatabase)
+        ));
+    }
 }
@@ -0,0 +1,122 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by the library.
+#[derive(Debug)]
+pub enum Error {
+    /// The password (or the key file) does not match the database, or the
+    /// salt or key derivation parameters in its header were modified, see
+    /// [`crate::encrypted_file::decrypt`].
+    WrongPassword,
+    /// A backup does not decrypt with the current key, most likely because
+    /// it was made before the password was changed; see
+    /// `Db::change_password`.
+    BackupKeyChanged,
+    /// The database was created with a key file, but none was given.
+    KeyFileRequired,
+    /// The file does not start with the cred-man magic bytes.
+    NotADatabase,
+    /// The file was written by a newer version of cred-man.
+    UnsupportedVersion(i32),
+    /// The header refers to a feature (flag, key derivation function) this
+    /// version does not know about, or has invalid parameters for it.
+    UnsupportedFormat(String),
+    /// The file is shorter than its header requires.
+    Truncated,
+    /// The key is right, but the header or the ciphertext fail
+    /// authentication: the file was modified or damaged.
+    Tampered,
+    /// The file decrypts, but its contents are not a valid database.
+    Corrupt(String),
+    /// Another process has the database open for writing; holds a
+    /// description of that process.
+    Locked(String),
+    /// The database was opened read-only and can not be saved.
+    ReadOnly,
+    /// The file was changed by someone else since it was loaded; see
+    /// `Db::reload`, `Db::overwrite` and `Db::merge`.
+    Conflict,
+    /// An attachment, or all attachments together, would exceed `limit`
+    /// bytes; see the `attachment` module.
+    AttachmentTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    /// A password or passphrase policy that nothing can be generated with,
+    /// or that could not be parsed; see the `generator` module.
+    InvalidPolicy(String),
+    /// A one-time code field that is not a valid `otpauth://` URI or base32
+    /// secret; see the `otp` module.
+    InvalidOtp(String),
+    Io(io::Error),
+}
+
+impl Error {
+    /// Process exit code for command-line frontends, so that scripts can
+    /// tell the failure reasons apart.
+    #[must_use]
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io(_) | Error::AttachmentTooLarge { .. } => 1,
+            Error::WrongPassword | Error::BackupKeyChanged => 2,
+            Error::KeyFileRequired => 3,
+            Error::NotADatabase | Error::Truncated | Error::Tampered | Error::Corrupt(_) => 4,
+            Error::UnsupportedVersion(_) | Error::UnsupportedFormat(_) => 5,
+            Error::Locked(_) | Error::ReadOnly | Error::Conflict => 6,
+            Error::InvalidPolicy(_) | Error::InvalidOtp(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::WrongPassword => write!(f, "Wrong password"),
+            Error::BackupKeyChanged => write!(f, "Backup encrypted with a previous password"),
+            Error::KeyFileRequired => {
+                write!(f, "This database requires a key file (--key-file <path>)")
+            }
+            Error::NotADatabase => write!(f, "Not a credentials database (MAGIC mismatch)"),
+            Error::UnsupportedVersion(ver) => {
+                write!(f, "Unsupported credentials database version: {ver}")
+            }
+            Error::UnsupportedFormat(msg) => {
+                write!(f, "Unsupported credentials database format: {msg}")
+            }
+            Error::Truncated => write!(f, "Credentials database file is truncated"),
+            Error::Tampered => write!(
+                f,
+                "Credentials database failed authentication: the file was modified or damaged"
+            ),
+            Error::Corrupt(msg) => write!(f, "Credentials database is corrupt: {msg}"),
+            Error::Locked(holder) => write!(f, "Credentials database is locked by {holder}"),
+            Error::ReadOnly => write!(f, "Credentials database is opened read-only"),
+            Error::Conflict => write!(
+                f,
+                "Credentials database was changed by another program since it was loaded"
+            ),
+            Error::AttachmentTooLarge { size, limit } => write!(
+                f,
+                "Attachments of {size} bytes exceed the limit of {limit} bytes"
+            ),
+            Error::InvalidPolicy(msg) => write!(f, "Invalid policy: {msg}"),
+            Error::InvalidOtp(msg) => write!(f, "Invalid one-time code seed: {msg}"),
+            Error::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
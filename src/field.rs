@@ -0,0 +1,157 @@
+//! What the fields of a record hold, so that frontends can show usernames
+//! and URLs right away and mask only the secrets.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    /// Anything else; secret unless marked otherwise
+    Text,
+    Url,
+    Username,
+    Password,
+    Otp,
+    /// Free-form text such as recovery codes; secret unless marked otherwise
+    Note,
+}
+
+impl FieldKind {
+    pub const ALL: [FieldKind; 6] = [
+        FieldKind::Text,
+        FieldKind::Url,
+        FieldKind::Username,
+        FieldKind::Password,
+        FieldKind::Otp,
+        FieldKind::Note,
+    ];
+
+    /// Guesses the kind of a field from its name, for fields that have no
+    /// metadata (all fields of databases written by older versions). Only
+    /// whole words count, as separated by `_`, `-` or spaces.
+    #[must_use]
+    pub fn guess(name: &str) -> FieldKind {
+        let name = name.to_lowercase();
+        let tokens = name
+            .split(['_', '-', ' '])
+            .filter(|token| !token.is_empty())
+            .collect::<Vec<_>>();
+        let has = |words: &[&str]| tokens.iter().any(|token| words.contains(token));
+        if has(&["otp", "totp", "hotp", "2fa", "mfa"]) {
+            FieldKind::Otp
+        } else if has(&[
+            "pass",
+            "password",
+            "passwd",
+            "passphrase",
+            "pw",
+            "pwd",
+            "pin",
+            "secret",
+            "token",
+            "key",
+            "apikey",
+        ]) {
+            FieldKind::Password
+        } else if has(&["url", "site", "website", "host", "hostname", "link"]) {
+            FieldKind::Url
+        } else if has(&["user", "username", "login", "email", "mail", "account"]) {
+            FieldKind::Username
+        } else if has(&["note", "notes", "comment", "comments"]) {
+            FieldKind::Note
+        } else {
+            FieldKind::Text
+        }
+    }
+
+    /// Whether fields of this kind are masked unless marked otherwise.
+    #[must_use]
+    pub fn is_sensitive_by_default(self) -> bool {
+        matches!(
+            self,
+            FieldKind::Text | FieldKind::Password | FieldKind::Otp | FieldKind::Note
+        )
+    }
+}
+
+impl fmt::Display for FieldKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FieldKind::Text => "text",
+            FieldKind::Url => "url",
+            FieldKind::Username => "username",
+            FieldKind::Password => "password",
+            FieldKind::Otp => "otp",
+            FieldKind::Note => "note",
+        })
+    }
+}
+
+impl FromStr for FieldKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FieldKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| {
+                let kinds = FieldKind::ALL.map(|kind| kind.to_string());
+                format!(
+                    "unknown field kind {s:?}, expected one of: {}",
+                    kinds.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldMeta {
+    pub kind: FieldKind,
+    /// Masked until explicitly revealed
+    pub sensitive: bool,
+}
+
+impl FieldMeta {
+    /// Metadata for a field that has none stored.
+    #[must_use]
+    pub fn guess(name: &str) -> FieldMeta {
+        FieldMeta::of_kind(FieldKind::guess(name))
+    }
+
+    #[must_use]
+    pub fn of_kind(kind: FieldKind) -> FieldMeta {
+        FieldMeta {
+            kind,
+            sensitive: kind.is_sensitive_by_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kinds_are_guessed_from_names() {
+        assert_eq!(FieldKind::guess("Password"), FieldKind::Password);
+        assert_eq!(FieldKind::guess("username"), FieldKind::Username);
+        assert_eq!(FieldKind::guess("url"), FieldKind::Url);
+        assert_eq!(FieldKind::guess("totp"), FieldKind::Otp);
+        assert!(FieldMeta::guess("security question").sensitive);
+        assert!(!FieldMeta::guess("login").sensitive);
+        assert!(FieldMeta::guess("notes").sensitive);
+        assert_eq!(FieldKind::guess("API_Key"), FieldKind::Password);
+        assert_eq!(FieldKind::guess("e-mail"), FieldKind::Username);
+        assert_eq!(FieldKind::guess("recovery codes"), FieldKind::Text);
+    }
+
+    #[test]
+    fn parts_of_words_are_not_guessed_from() {
+        assert_eq!(FieldKind::guess("keyboard"), FieldKind::Text);
+        assert_eq!(FieldKind::guess("monkey"), FieldKind::Text);
+        assert_eq!(FieldKind::guess("shipping"), FieldKind::Text);
+        assert_eq!(FieldKind::guess("ghost"), FieldKind::Text);
+    }
+}
@@ -0,0 +1,400 @@
+//! Random passwords, so that new credentials don't have to be made up, and
+//! passphrases of dictionary words for those that have to be remembered or
+//! typed (master passwords, Wi-Fi keys).
+//!
+//! Sites disagree on what a password may contain, so a record can keep its
+//! own [`PasswordPolicy`], see [`crate::DbRecord::password_policy`].
+//!
+//! The wordlists are numbered by dice rolls like the EFF ones (4 dice for
+//! [`Wordlist::Short`], 5 for [`Wordlist::Long`]), so that a passphrase can
+//! also be rolled by hand.
+
+use crate::Error;
+use getrandom::getrandom;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{}~";
+/// Characters that are easily confused with each other when read or typed.
+const AMBIGUOUS: &str = "Il1|O0o";
+
+pub const MAX_PASSWORD_LENGTH: usize = 1024;
+pub const MAX_PASSPHRASE_WORDS: usize = 64;
+
+const SHORT_WORDLIST: &str = include_str!("wordlists/short.txt");
+const LONG_WORDLIST: &str = include_str!("wordlists/long.txt");
+
+// One switch per character class, like the check boxes of a generator.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    /// Leave out [`AMBIGUOUS`] characters
+    pub exclude_ambiguous: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// The character classes in use; a generated password has at least one
+    /// character of each.
+    fn classes(&self) -> Vec<Vec<char>> {
+        [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, chars)| {
+            chars
+                .chars()
+                .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect()
+        })
+        .collect()
+    }
+
+    /// Bits of entropy of the passwords generated with this policy; a bit
+    /// less in fact, as every class has to be present.
+    #[must_use]
+    pub fn entropy(&self) -> f64 {
+        let alphabet = self.classes().concat().len();
+        self.length as f64 * (alphabet as f64).log2()
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let classes = self.classes().len();
+        if classes == 0 {
+            return Err(Error::InvalidPolicy(
+                "no character classes selected".to_string(),
+            ));
+        }
+        if self.length < classes || self.length > MAX_PASSWORD_LENGTH {
+            return Err(Error::InvalidPolicy(format!(
+                "length must be between {classes} and {MAX_PASSWORD_LENGTH}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Same words as [`PasswordPolicy::from_str`] takes, e.g.
+/// `16 lower upper digits unambiguous`.
+impl fmt::Display for PasswordPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.length)?;
+        for (enabled, word) in [
+            (self.lowercase, "lower"),
+            (self.uppercase, "upper"),
+            (self.digits, "digits"),
+            (self.symbols, "symbols"),
+            (self.exclude_ambiguous, "unambiguous"),
+        ] {
+            if enabled {
+                write!(f, " {word}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PasswordPolicy {
+    type Err = Error;
+
+    /// Space-separated words, in any order: the length, the character
+    /// classes (`lower`, `upper`, `digits`, `symbols`; all of them if none
+    /// is given) and `unambiguous`. Anything left out is as in
+    /// [`PasswordPolicy::default`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = PasswordPolicy {
+            lowercase: false,
+            uppercase: false,
+            digits: false,
+            symbols: false,
+            ..PasswordPolicy::default()
+        };
+        for word in s.split_whitespace() {
+            match word {
+                "lower" => policy.lowercase = true,
+                "upper" => policy.uppercase = true,
+                "digits" => policy.digits = true,
+                "symbols" => policy.symbols = true,
+                "unambiguous" => policy.exclude_ambiguous = true,
+                _ => {
+                    policy.length = usize::from_str(word).map_err(|_| {
+                        Error::InvalidPolicy(format!("unknown password policy word {word:?}"))
+                    })?;
+                }
+            }
+        }
+        if !(policy.lowercase || policy.uppercase || policy.digits || policy.symbols) {
+            let default = PasswordPolicy::default();
+            policy.lowercase = default.lowercase;
+            policy.uppercase = default.uppercase;
+            policy.digits = default.digits;
+            policy.symbols = default.symbols;
+        }
+        policy.check()?;
+        Ok(policy)
+    }
+}
+
+/// A password following `policy`, with at least one character of each of
+/// its classes.
+pub fn generate(policy: &PasswordPolicy) -> Result<Zeroizing<String>, Error> {
+    policy.check()?;
+    let classes = policy.classes();
+    let alphabet = classes.concat();
+    loop {
+        let password = Zeroizing::new(
+            (0..policy.length)
+                .map(|_| alphabet[random_below(alphabet.len())])
+                .collect::<String>(),
+        );
+        // Rarely more than a couple of attempts, as `check` makes sure there
+        // is room for every class.
+        if classes
+            .iter()
+            .all(|class| password.chars().any(|c| class.contains(&c)))
+        {
+            return Ok(password);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wordlist {
+    /// 1296 words of up to 5 letters, quicker to type
+    Short,
+    /// 7776 words of up to 9 letters
+    Long,
+}
+
+impl Wordlist {
+    #[must_use]
+    pub fn words(self) -> Vec<&'static str> {
+        let list = match self {
+            Wordlist::Short => SHORT_WORDLIST,
+            Wordlist::Long => LONG_WORDLIST,
+        };
+        // Each line is the dice roll and the word.
+        list.lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capitalization {
+    Lower,
+    /// First letter of each word
+    Title,
+    Upper,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PassphrasePolicy {
+    pub words: usize,
+    pub wordlist: Wordlist,
+    pub separator: String,
+    pub capitalization: Capitalization,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        PassphrasePolicy {
+            words: 6,
+            wordlist: Wordlist::Long,
+            separator: "-".to_string(),
+            capitalization: Capitalization::Lower,
+        }
+    }
+}
+
+impl PassphrasePolicy {
+    /// Bits of entropy of the passphrases generated with this policy.
+    #[must_use]
+    pub fn entropy(&self) -> f64 {
+        self.words as f64 * (self.wordlist.words().len() as f64).log2()
+    }
+}
+
+/// Same words as [`PassphrasePolicy::from_str`] takes, e.g.
+/// `6 long title sep=-`.
+impl fmt::Display for PassphrasePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wordlist = match self.wordlist {
+            Wordlist::Short => "short",
+            Wordlist::Long => "long",
+        };
+        let capitalization = match self.capitalization {
+            Capitalization::Lower => "lower",
+            Capitalization::Title => "title",
+            Capitalization::Upper => "upper",
+        };
+        let separator = match self.separator.as_str() {
+            " " => "space",
+            separator => separator,
+        };
+        write!(
+            f,
+            "{} {wordlist} {capitalization} sep={separator}",
+            self.words
+        )
+    }
+}
+
+impl FromStr for PassphrasePolicy {
+    type Err = Error;
+
+    /// Space-separated words, in any order: the number of words, the
+    /// wordlist (`short` or `long`), the capitalization (`lower`, `title`
+    /// or `upper`) and `sep=<separator>`, where the separator may be
+    /// `space` but not empty or contain letters: words that run into each
+    /// other could be split differently, and the passphrase would be weaker
+    /// than [`PassphrasePolicy::entropy`] says. Anything left out is as in
+    /// [`PassphrasePolicy::default`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = PassphrasePolicy::default();
+        for word in s.split_whitespace() {
+            match word {
+                "short" => policy.wordlist = Wordlist::Short,
+                "long" => policy.wordlist = Wordlist::Long,
+                "lower" => policy.capitalization = Capitalization::Lower,
+                "title" => policy.capitalization = Capitalization::Title,
+                "upper" => policy.capitalization = Capitalization::Upper,
+                "sep=space" => policy.separator = " ".to_string(),
+                _ => {
+                    if let Some(separator) = word.strip_prefix("sep=") {
+                        policy.separator = separator.to_string();
+                    } else {
+                        policy.words = usize::from_str(word).map_err(|_| {
+                            Error::InvalidPolicy(format!("unknown passphrase policy word {word:?}"))
+                        })?;
+                    }
+                }
+            }
+        }
+        if policy.words == 0 || policy.words > MAX_PASSPHRASE_WORDS {
+            return Err(Error::InvalidPolicy(format!(
+                "number of words must be between 1 and {MAX_PASSPHRASE_WORDS}"
+            )));
+        }
+        if policy.separator.is_empty() || policy.separator.chars().any(char::is_alphabetic) {
+            return Err(Error::InvalidPolicy(
+                "the separator must not be empty or contain letters".to_string(),
+            ));
+        }
+        Ok(policy)
+    }
+}
+
+/// A passphrase of words picked at random from the policy's wordlist.
+#[must_use]
+pub fn generate_passphrase(policy: &PassphrasePolicy) -> Zeroizing<String> {
+    let words = policy.wordlist.words();
+    let mut passphrase = Zeroizing::new(String::new());
+    for i in 0..policy.words {
+        if i > 0 {
+            passphrase.push_str(&policy.separator);
+        }
+        let word = words[random_below(words.len())];
+        match policy.capitalization {
+            Capitalization::Lower => passphrase.push_str(word),
+            Capitalization::Title => {
+                let mut chars = word.chars();
+                passphrase.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                passphrase.extend(chars);
+            }
+            Capitalization::Upper => passphrase.push_str(&word.to_uppercase()),
+        }
+    }
+    passphrase
+}
+
+/// Uniformly distributed in `0..n`.
+fn random_below(n: usize) -> usize {
+    let n = u32::try_from(n).expect("small range");
+    // Values at and above `limit` would make the lower results more likely.
+    let limit = u32::MAX - u32::MAX % n;
+    loop {
+        let mut bytes = [0; 4];
+        getrandom(&mut bytes).expect("getrandom() failed");
+        let value = u32::from_le_bytes(bytes);
+        if value < limit {
+            return (value % n) as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn passwords_follow_the_policy() {
+        let policy = PasswordPolicy::from_str("12 digits upper unambiguous").expect("valid policy");
+        assert_eq!(policy.to_string(), "12 upper digits unambiguous");
+        for _ in 0..100 {
+            let password = generate(&policy).expect("valid policy");
+            assert_eq!(password.len(), 12);
+            assert!(password
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()));
+            assert!(password.chars().any(|c| c.is_ascii_digit()));
+            assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+        }
+        assert!(matches!(
+            PasswordPolicy::from_str("3"),
+            Err(Error::InvalidPolicy(_))
+        ));
+        assert!(PasswordPolicy::from_str("8 emoji").is_err());
+    }
+
+    #[test]
+    fn passphrases_follow_the_policy() {
+        assert_eq!(Wordlist::Short.words().len(), 6 * 6 * 6 * 6);
+        assert_eq!(Wordlist::Long.words().len(), 6 * 6 * 6 * 6 * 6);
+        let long = Wordlist::Long.words();
+        assert!(long.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let policy = PassphrasePolicy::from_str("4 title sep=space").expect("valid policy");
+        assert_eq!(policy.to_string(), "4 long title sep=space");
+        for separator in ["sep=", "sep=x", "sep=-and-"] {
+            assert!(matches!(
+                PassphrasePolicy::from_str(separator),
+                Err(Error::InvalidPolicy(_))
+            ));
+        }
+        assert!((policy.entropy() - 4.0 * 7776f64.log2()).abs() < 1e-9);
+        let passphrase = generate_passphrase(&policy);
+        let words = passphrase.split(' ').collect::<Vec<_>>();
+        assert_eq!(words.len(), 4);
+        for word in words {
+            assert!(word.starts_with(|c: char| c.is_ascii_uppercase()));
+            assert!(long.contains(&word.to_lowercase().as_str()));
+        }
+    }
+}
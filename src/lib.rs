@@ -22,46 +22,539 @@
 )]
 
 use chrono::naive::NaiveDateTime;
-use chrono::Local;
+use chrono::{DateTime, Local, TimeZone, Utc};
+use indexmap::IndexMap;
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, Zeroizing};
 
+pub mod attachment;
+pub mod audit;
+pub mod backup;
+pub mod breach;
 pub mod encrypted_file;
+mod error;
+pub mod field;
+pub mod generator;
+mod lock;
+pub mod otp;
+pub mod secret;
+pub mod strength;
+
+use attachment::{Attachment, AttachmentDTO};
+use backup::{Backup, RetentionPolicy};
+pub use encrypted_file::{CompositeKey, KdfAlgorithm};
+use encrypted_file::{DerivedKey, KdfParams};
+pub use error::Error;
+pub use field::{FieldKind, FieldMeta};
+pub use generator::PasswordPolicy;
+use lock::DbLock;
 
 pub struct DbRecord {
     pub key: String,
-    pub timestamp: NaiveDateTime,
-    pub value: BTreeMap<String, String>,
+    pub created: DateTime<Utc>,
+    /// When anything in the record was last changed, its tags or folder for
+    /// example
+    pub modified: DateTime<Utc>,
+    /// When `value` was last replaced, see [`DbRecord::new_revision`]
+    pub value_changed: DateTime<Utc>,
+    /// When the value was last looked at, see [`Db::access`]; `None` if never
+    pub accessed: Option<DateTime<Utc>>,
+    /// Fields in the order they are shown, see [`DbRecord::move_field`]
+    pub value: IndexMap<String, String>,
+    /// Metadata of the fields in `value`; fields without an entry get
+    /// [`FieldMeta::guess`]. See [`DbRecord::field_meta`].
+    pub fields: BTreeMap<String, FieldMeta>,
+    /// Previous values, oldest first; at most [`HISTORY_LIMIT`] of them.
+    pub history: Vec<Revision>,
+    pub tags: BTreeSet<String>,
+    /// Files kept with the record, by name; see [`Db::attach`]
+    pub attachments: BTreeMap<String, Attachment>,
+    /// Slash-separated path like `work/servers`, see [`DbRecord::set_folder`]
+    pub folder: Option<String>,
+    /// Rules of the site for new passwords, if they differ from
+    /// [`PasswordPolicy::default`]
+    pub password_policy: Option<PasswordPolicy>,
+}
+
+/// How many previous values a record keeps.
+pub const HISTORY_LIMIT: usize = 10;
+
+impl DbRecord {
+    #[must_use]
+    pub fn new(key: String) -> DbRecord {
+        let now = Utc::now();
+        DbRecord {
+            key,
+            created: now,
+            modified: now,
+            value_changed: now,
+            accessed: None,
+            value: IndexMap::new(),
+            fields: BTreeMap::new(),
+            history: Vec::new(),
+            tags: BTreeSet::new(),
+            attachments: BTreeMap::new(),
+            folder: None,
+            password_policy: None,
+        }
+    }
+
+    #[must_use]
+    pub fn field_meta(&self, name: &str) -> FieldMeta {
+        self.fields
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| FieldMeta::guess(name))
+    }
+
+    /// Whether the field should be masked until explicitly revealed.
+    #[must_use]
+    pub fn is_sensitive(&self, name: &str) -> bool {
+        self.field_meta(name).sensitive
+    }
+
+    pub fn set_field_meta(&mut self, name: &str, meta: FieldMeta) {
+        if self.field_meta(name) != meta {
+            self.fields.insert(name.to_string(), meta);
+            self.modified = Utc::now();
+        }
+    }
+
+    /// Renames a field of `value` in place, together with its metadata,
+    /// recording a revision (see [`DbRecord::new_revision`]). Returns `false`
+    /// if there is no field `from`.
+    pub fn rename_field(&mut self, from: &str, to: &str) -> bool {
+        if !self.value.contains_key(from) {
+            return false;
+        }
+        if from == to {
+            return true;
+        }
+        self.new_revision();
+        let (idx, _, value) = self.value.shift_remove_full(from).expect("checked above");
+        if let Some(mut replaced) = self.value.shift_remove(to) {
+            replaced.zeroize();
+        }
+        self.value
+            .shift_insert(idx.min(self.value.len()), to.to_string(), value);
+        if let Some(meta) = self.fields.remove(from) {
+            self.fields.insert(to.to_string(), meta);
+        }
+        true
+    }
+
+    /// Moves a field of `value` to position `to` (or the last one, if `to`
+    /// is past the end). Returns `false` if there is no such field.
+    pub fn move_field(&mut self, name: &str, to: usize) -> bool {
+        let Some(from) = self.value.get_index_of(name) else {
+            return false;
+        };
+        let to = to.min(self.value.len() - 1);
+        if from != to {
+            self.value.move_index(from, to);
+            self.modified = Utc::now();
+        }
+        true
+    }
+
+    /// Removes a field of `value` together with its metadata, recording a
+    /// revision (see [`DbRecord::new_revision`]). Returns `false` if there
+    /// is no such field.
+    pub fn remove_field(&mut self, name: &str) -> bool {
+        if !self.value.contains_key(name) {
+            return false;
+        }
+        self.new_revision();
+        self.fields.remove(name);
+        if let Some(mut value) = self.value.shift_remove(name) {
+            value.zeroize();
+        }
+        true
+    }
+
+    /// Returns `false` if the record already has the tag.
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let added = self.tags.insert(tag.to_string());
+        if added {
+            self.modified = Utc::now();
+        }
+        added
+    }
+
+    /// Returns `false` if the record does not have the tag.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let removed = self.tags.remove(tag);
+        if removed {
+            self.modified = Utc::now();
+        }
+        removed
+    }
+
+    /// Moves the record to `folder`; empty path components are dropped, so
+    /// `/work//servers/` is `work/servers` and `/` is no folder at all.
+    pub fn set_folder(&mut self, folder: &str) {
+        let folder = folder
+            .split('/')
+            .map(str::trim)
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+        let folder = (!folder.is_empty()).then_some(folder);
+        if folder != self.folder {
+            self.folder = folder;
+            self.modified = Utc::now();
+        }
+    }
+
+    /// Moves the current value to the history and updates the modification
+    /// time. Call before changing `value`.
+    pub fn new_revision(&mut self) {
+        self.history.push(Revision {
+            timestamp: self.value_changed,
+            value: self.value.clone(),
+        });
+        if self.history.len() > HISTORY_LIMIT {
+            let excess = self.history.len() - HISTORY_LIMIT;
+            self.history.drain(..excess);
+        }
+        self.modified = Utc::now();
+        self.value_changed = self.modified;
+    }
+
+    /// Makes the value of `history[idx]` current again; the value it
+    /// replaces goes to the history. Returns `false` if there is no such
+    /// revision.
+    pub fn restore_revision(&mut self, idx: usize) -> bool {
+        let Some(value) = self.history.get(idx).map(|r| r.value.clone()) else {
+            return false;
+        };
+        self.new_revision();
+        wipe_values(&mut self.value);
+        self.value = value;
+        true
+    }
+}
+
+impl Drop for DbRecord {
+    fn drop(&mut self) {
+        wipe_values(&mut self.value);
+    }
+}
+
+/// A previous value of a record.
+pub struct Revision {
+    /// When this value was set
+    pub timestamp: DateTime<Utc>,
+    pub value: IndexMap<String, String>,
+}
+
+impl Drop for Revision {
+    fn drop(&mut self) {
+        wipe_values(&mut self.value);
+    }
+}
+
+fn wipe_values(value: &mut IndexMap<String, String>) {
+    for (mut name, mut value) in std::mem::take(value) {
+        name.zeroize();
+        value.zeroize();
+    }
 }
 
 pub struct Db {
     pub data: BTreeMap<String, DbRecord>,
-    password: String,
+    key: CompositeKey,
     location: DbLocation,
+    kdf: KdfParams,
+    /// Key derived on unlock (or on the first save), reused by saves as long
+    /// as it was derived with `kdf`.
+    derived_key: Option<DerivedKey>,
+    /// `None` if the database was opened read-only.
+    lock: Option<DbLock>,
+    /// Hash of `keys.db` as last loaded or saved, `None` if there was no
+    /// such file. Tells whether someone else has written it since.
+    file_hash: Option<FileHash>,
+    /// Applied to the backups after every save; `None` until the user has
+    /// chosen one, so that no backup is deleted unasked.
+    retention: Option<RetentionPolicy>,
+    /// Why pruning after the last save failed, see [`Db::take_prune_error`]
+    prune_error: Option<Error>,
+    /// Whether [`Db::access`] has changed access times since the last save.
+    access_times_changed: bool,
 }
 
 impl Db {
-    fn new(password: String, location: DbLocation) -> Db {
+    fn new(key: CompositeKey, location: DbLocation, kdf: KdfParams) -> Db {
         Db {
             data: BTreeMap::new(),
-            password,
+            key,
             location,
+            kdf,
+            derived_key: None,
+            lock: None,
+            file_hash: None,
+            retention: None,
+            prune_error: None,
+            access_times_changed: false,
         }
     }
+
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.lock.is_none()
+    }
+
+    /// Key derivation function that will be used on the next save.
+    #[must_use]
+    pub fn kdf(&self) -> KdfAlgorithm {
+        self.kdf.algorithm()
+    }
+
+    /// Switches the database to another key derivation function;
+    /// takes effect on the next save.
+    pub fn set_kdf(&mut self, algorithm: KdfAlgorithm) {
+        self.kdf = algorithm.recommended_params();
+    }
+
+    /// All tags used by the records, sorted.
+    #[must_use]
+    pub fn tags(&self) -> BTreeSet<&str> {
+        self.data
+            .values()
+            .flat_map(|record| record.tags.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Attaches `data` to the record `key` as `name`, replacing the
+    /// attachment of that name, if any. Returns `false` if there is no such
+    /// record. Fails with [`Error::AttachmentTooLarge`] if the attachment is
+    /// larger than [`attachment::MAX_ATTACHMENT_SIZE`], or all attachments
+    /// together would be larger than
+    /// [`attachment::MAX_TOTAL_ATTACHMENTS_SIZE`].
+    pub fn attach(&mut self, key: &str, name: &str, data: Vec<u8>) -> Result<bool, Error> {
+        let attachment = Attachment::new(data)?;
+        let Some(record) = self.data.get(key) else {
+            return Ok(false);
+        };
+        let replaced = record.attachments.get(name).map_or(0, Attachment::len);
+        attachment::check_size(
+            self.attachments_size() - replaced + attachment.len(),
+            attachment::MAX_TOTAL_ATTACHMENTS_SIZE,
+        )?;
+        let record = self.data.get_mut(key).expect("checked above");
+        record.attachments.insert(name.to_string(), attachment);
+        record.modified = Utc::now();
+        Ok(true)
+    }
+
+    /// Total size of the attachments of all records, in bytes.
+    #[must_use]
+    pub fn attachments_size(&self) -> usize {
+        self.data
+            .values()
+            .flat_map(|record| record.attachments.values())
+            .map(Attachment::len)
+            .sum()
+    }
+
+    /// Looks up a record to show its value, updating its access time. The
+    /// access time is written with the next save, or by
+    /// [`Db::save_access_times`].
+    pub fn access(&mut self, key: &str) -> Option<&DbRecord> {
+        let record = self.data.get_mut(key)?;
+        record.accessed = Some(Utc::now());
+        self.access_times_changed = true;
+        Some(record)
+    }
 }
 
+/// Times are RFC 3339 in UTC. Files written by older versions only have
+/// `timestamp`, the local time of the last change, see
+/// [`parse_dto_timestamp`].
 #[derive(Serialize, Deserialize, Debug)]
 struct DbRecordDTO {
     key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<String>,
+    /// Missing in files written by older versions, which only had `modified`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value_changed: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    accessed: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    value: IndexMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<RevisionDTO>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<String, FieldMeta>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    tags: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    attachments: BTreeMap<String, AttachmentDTO>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    folder: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_policy: Option<PasswordPolicy>,
+}
+
+impl Drop for DbRecordDTO {
+    fn drop(&mut self) {
+        wipe_values(&mut self.value);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RevisionDTO {
     timestamp: String,
-    value: BTreeMap<String, String>,
+    value: IndexMap<String, String>,
+}
+
+impl Drop for RevisionDTO {
+    fn drop(&mut self) {
+        wipe_values(&mut self.value);
+    }
+}
+
+/// Format of the local times written by older versions.
+const LEGACY_DTO_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+fn format_dto_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// Parses an RFC 3339 time, or a local time written by an older version.
+fn parse_dto_timestamp(timestamp: &str) -> Result<DateTime<Utc>, Error> {
+    if let Ok(parsed) = DateTime::parse_from_rfc3339(timestamp) {
+        return Ok(parsed.with_timezone(&Utc));
+    }
+    let local = NaiveDateTime::parse_from_str(timestamp, LEGACY_DTO_TIME_FORMAT)
+        .map_err(|e| Error::Corrupt(format!("invalid timestamp \"{timestamp}\": {e}")))?;
+    // Times skipped by a DST change can't be converted; they can only come
+    // from a clock that was off anyway.
+    Ok(Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map_or_else(|| local.and_utc(), |t| t.with_timezone(&Utc)))
+}
+
+impl DbRecordDTO {
+    fn from_record(r: &DbRecord) -> DbRecordDTO {
+        DbRecordDTO {
+            key: r.key.clone(),
+            created: Some(format_dto_timestamp(r.created)),
+            modified: Some(format_dto_timestamp(r.modified)),
+            value_changed: Some(format_dto_timestamp(r.value_changed)),
+            accessed: r.accessed.map(format_dto_timestamp),
+            timestamp: None,
+            value: r.value.clone(),
+            history: r
+                .history
+                .iter()
+                .map(|rev| RevisionDTO {
+                    timestamp: format_dto_timestamp(rev.timestamp),
+                    value: rev.value.clone(),
+                })
+                .collect(),
+            fields: r
+                .fields
+                .iter()
+                .filter(|(name, _)| r.value.contains_key(*name))
+                .map(|(name, meta)| (name.clone(), *meta))
+                .collect(),
+            tags: r.tags.clone(),
+            attachments: r
+                .attachments
+                .iter()
+                .map(|(name, attachment)| {
+                    (name.clone(), AttachmentDTO::from_attachment(attachment))
+                })
+                .collect(),
+            folder: r.folder.clone(),
+            password_policy: r.password_policy,
+        }
+    }
+
+    /// Moves the contents out, leaving nothing behind to be wiped.
+    fn take_record(&mut self) -> Result<DbRecord, Error> {
+        let mut history = Vec::with_capacity(self.history.len());
+        for rev in &mut self.history {
+            history.push(Revision {
+                timestamp: parse_dto_timestamp(&rev.timestamp)?,
+                value: std::mem::take(&mut rev.value),
+            });
+        }
+        let modified = self
+            .modified
+            .as_ref()
+            .or(self.timestamp.as_ref())
+            .ok_or_else(|| Error::Corrupt(format!("no timestamp for \"{}\"", self.key)))
+            .and_then(|t| parse_dto_timestamp(t))?;
+        let created = match &self.created {
+            Some(created) => parse_dto_timestamp(created)?,
+            None => modified,
+        };
+        let value_changed = match &self.value_changed {
+            Some(value_changed) => parse_dto_timestamp(value_changed)?,
+            None => modified,
+        };
+        let accessed = self
+            .accessed
+            .as_deref()
+            .map(parse_dto_timestamp)
+            .transpose()?;
+        let mut attachments = BTreeMap::new();
+        for (name, attachment) in &self.attachments {
+            attachments.insert(name.clone(), attachment.to_attachment(name, modified)?);
+        }
+        Ok(DbRecord {
+            key: std::mem::take(&mut self.key),
+            created,
+            modified,
+            value_changed,
+            accessed,
+            value: std::mem::take(&mut self.value),
+            fields: std::mem::take(&mut self.fields),
+            history,
+            tags: std::mem::take(&mut self.tags),
+            attachments,
+            folder: self.folder.take(),
+            password_policy: self.password_policy,
+        })
+    }
+}
+
+/// Records in the JSON format of the database contents, for plain-text
+/// exports that [`records_from_json`] reads back.
+#[must_use]
+pub fn records_to_json<'a>(records: impl IntoIterator<Item = &'a DbRecord>) -> Zeroizing<String> {
+    let dto: Vec<DbRecordDTO> = records.into_iter().map(DbRecordDTO::from_record).collect();
+    Zeroizing::new(serde_json::to_string_pretty(&dto).expect("DbRecordDTO is json-serializable"))
 }
 
-const DTO_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+/// Reads records written by [`records_to_json`], or exported by older
+/// versions. Fails with [`Error::Corrupt`] if anything in `json` is invalid.
+pub fn records_from_json(json: &str) -> Result<Vec<DbRecord>, Error> {
+    let dto: Vec<DbRecordDTO> =
+        serde_json::from_str(json).map_err(|e| Error::Corrupt(format!("invalid json: {e}")))?;
+    let mut records = Vec::with_capacity(dto.len());
+    for mut r in dto {
+        let mut record = r.take_record()?;
+        if let Some(folder) = record.folder.take() {
+            record.set_folder(&folder);
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
 
 #[derive(Clone)]
 pub enum DbLocation {
@@ -69,11 +562,22 @@ pub enum DbLocation {
     SpecifiedDirectory(PathBuf),
 }
 
+impl DbLocation {
+    /// Whether there is a database yet; [`Db::load`] creates a new one
+    /// otherwise.
+    #[must_use]
+    pub fn exists(&self) -> bool {
+        get_db_path(PathKind::Main, self).exists()
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum PathKind {
     Main,
     Temp,
     Backup,
+    Lock,
+    Retention,
 }
 
 fn get_db_path(kind: PathKind, location: &DbLocation) -> PathBuf {
@@ -92,110 +596,648 @@ fn get_db_path(kind: PathKind, location: &DbLocation) -> PathBuf {
     path.push(match kind {
         PathKind::Main => "keys.db".to_string(),
         PathKind::Temp => "keys.tmp.db".to_string(),
-        PathKind::Backup => format!("keys.backup.{}.db", Local::now().format("%Y%m%d_%H%M%S")),
+        PathKind::Lock => "keys.lock".to_string(),
+        PathKind::Retention => "keys.retention.json".to_string(),
+        PathKind::Backup => format!(
+            "{}{}{}",
+            backup::BACKUP_PREFIX,
+            Local::now().format(backup::BACKUP_TIME_FORMAT),
+            backup::BACKUP_SUFFIX
+        ),
     });
     path
 }
 
-pub enum DbLoadResult {
-    Loaded(Db),
-    WrongPassword,
-}
-
 impl Db {
-    pub fn load(location: &DbLocation, password: &str) -> io::Result<DbLoadResult> {
+    /// Opens the database for writing. Fails with [`Error::Locked`] if
+    /// another process has it open for writing.
+    pub fn load(location: &DbLocation, key: &CompositeKey) -> Result<Db, Error> {
+        std::fs::create_dir_all(db_dir(location))?;
+        let lock = DbLock::acquire(&get_db_path(PathKind::Lock, location))?;
+        let mut db = Db::load_read_only(location, key)?;
+        db.lock = Some(lock);
+        Ok(db)
+    }
+
+    /// Opens the database without taking the lock; [`Db::save`] will fail.
+    pub fn load_read_only(location: &DbLocation, key: &CompositeKey) -> Result<Db, Error> {
         let path = get_db_path(PathKind::Main, location);
-        match fs::metadata(&path) {
-            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+        let retention = backup::read_policy(&get_db_path(PathKind::Retention, location))?;
+        let mut db = match read_file(&path, key, None)? {
+            None => {
                 println!(
                     "Path {} not found, will create new database",
                     path.to_string_lossy()
                 );
-                let dir = path.parent().expect("get_db_path returns path with dir");
-                std::fs::create_dir_all(dir)?;
-                let db = Db::new(password.to_owned(), location.clone());
-                Ok(DbLoadResult::Loaded(db))
+                Db::new(key.clone(), location.clone(), KdfParams::recommended())
             }
-            Err(e) => Err(e),
-            Ok(_) => {
-                let data = encrypted_file::parse_file(&path)?;
-                match encrypted_file::decrypt(&data, password) {
-                    None => Ok(DbLoadResult::WrongPassword),
-                    Some(contents) => {
-                        let dto: Vec<DbRecordDTO> =
-                            serde_json::from_str(&contents).map_err(|e| {
-                                io::Error::new(
-                                    io::ErrorKind::Other,
-                                    format!("Db contains invalid json: {e}"),
-                                )
-                            })?;
-                        let mut db = Db::new(password.to_owned(), location.clone());
-                        for r in dto {
-                            let k = r.key.clone();
-                            db.data.insert(
-                                k,
-                                DbRecord {
-                                    key: r.key,
-                                    timestamp: NaiveDateTime::parse_from_str(
-                                        &r.timestamp,
-                                        DTO_TIME_FORMAT,
-                                    ).map_err(|e| {
-                                        io::Error::new(
-                                            io::ErrorKind::Other,
-                                            format!("Db contains invalid json: invalid timestamp \"{}\": {e}", r.timestamp),
-                                        )
-                                    })?,
-                                    value: r.value,
-                                },
-                            );
-                        }
-                        Ok(DbLoadResult::Loaded(db))
-                    }
-                }
+            Some(file) => {
+                // Keep the algorithm the file was written with, but
+                // with the current parameters for it.
+                let mut db = Db::new(
+                    key.clone(),
+                    location.clone(),
+                    file.derived_key.kdf().algorithm().recommended_params(),
+                );
+                db.data = file.records;
+                db.derived_key = Some(file.derived_key);
+                db.file_hash = Some(file.hash);
+                db
             }
+        };
+        db.retention = retention;
+        Ok(db)
+    }
+
+    pub fn save(&mut self) -> Result<(), Error> {
+        self.write(true)
+    }
+
+    /// Saves if [`Db::access`] has changed access times since the last
+    /// save. Does nothing for a read-only database. Unlike [`Db::save`], does
+    /// not make a backup: losing access times is no loss.
+    pub fn save_access_times(&mut self) -> Result<(), Error> {
+        if !self.access_times_changed || self.is_read_only() {
+            return Ok(());
         }
+        self.write(false)
     }
 
-    pub fn save(&self) -> io::Result<()> {
+    fn write(&mut self, backup: bool) -> Result<(), Error> {
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
         let main_path = get_db_path(PathKind::Main, &self.location);
         let backup_path = get_db_path(PathKind::Backup, &self.location);
         let temp_path = get_db_path(PathKind::Temp, &self.location);
+        if file_hash(&main_path)? != self.file_hash {
+            return Err(Error::Conflict);
+        }
         match fs::metadata(&main_path) {
-            Ok(_) => {
+            Ok(_) if backup => {
                 fs::copy(&main_path, backup_path)?;
             }
+            Ok(_) => (),
             Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
             Err(e) => {
-                return Err(e);
+                return Err(e.into());
             }
         }
-        let mut dto: Vec<DbRecordDTO> = Vec::new();
-        for r in self.data.values() {
-            dto.push(DbRecordDTO {
-                key: r.key.clone(),
-                timestamp: format!("{}", r.timestamp.format(DTO_TIME_FORMAT)),
-                value: r.value.clone(),
-            });
+        let dto: Vec<DbRecordDTO> = self.data.values().map(DbRecordDTO::from_record).collect();
+        let contents =
+            Zeroizing::new(serde_json::to_string(&dto).expect("DbRecordDTO is json-serializable"));
+        // self.kdf always holds the current parameters, so that files created
+        // with older (weaker) parameters get upgraded on the next save.
+        if self
+            .derived_key
+            .as_ref()
+            .is_none_or(|derived_key| derived_key.kdf() != self.kdf)
+        {
+            self.derived_key = Some(DerivedKey::new(&self.key, self.kdf)?);
         }
-        let contents = serde_json::to_string(&dto).expect("DbRecordDTO is json-serializable");
-        let data = encrypted_file::encrypt(&contents, &self.password);
+        let derived_key = self.derived_key.as_ref().expect("derived above");
+        let data = encrypted_file::encrypt_with_key(&contents, derived_key);
         encrypted_file::write_to_file(&temp_path, &data)?;
         fs::rename(&temp_path, &main_path)?;
+        self.file_hash = file_hash(&main_path)?;
+        self.access_times_changed = false;
+        // The file is saved by now: a failure to prune must not make callers
+        // roll back to a state that no longer matches it.
+        if let Some(policy) = self.retention {
+            self.prune_error = self.prune_backups(&policy).err();
+        }
         Ok(())
     }
+
+    /// Why deleting old backups after the last save failed, if it did; the
+    /// save itself succeeded.
+    pub fn take_prune_error(&mut self) -> Option<Error> {
+        self.prune_error.take()
+    }
+
+    /// Saves over changes someone else has written since the database was
+    /// loaded (see [`Error::Conflict`]); they are only kept in the backup.
+    pub fn overwrite(&mut self) -> Result<(), Error> {
+        let old_file_hash = self.file_hash;
+        self.file_hash = file_hash(&get_db_path(PathKind::Main, &self.location))?;
+        let result = self.save();
+        if result.is_err() {
+            self.file_hash = old_file_hash;
+        }
+        result
+    }
+
+    /// Replaces the records with the ones currently on disk, dropping
+    /// unsaved changes.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let path = get_db_path(PathKind::Main, &self.location);
+        match read_file(&path, &self.key, self.derived_key.as_ref())? {
+            None => {
+                self.data.clear();
+                self.file_hash = None;
+            }
+            Some(file) => {
+                self.data = file.records;
+                self.derived_key = Some(file.derived_key);
+                self.file_hash = Some(file.hash);
+            }
+        }
+        self.access_times_changed = false;
+        Ok(())
+    }
+
+    /// Merges the records currently on disk into the unsaved ones: records
+    /// changed on both sides keep the newer version. Records deleted on just
+    /// one side come back. After merging, [`Db::save`] no longer conflicts.
+    pub fn merge(&mut self) -> Result<(), Error> {
+        let path = get_db_path(PathKind::Main, &self.location);
+        let Some(file) = read_file(&path, &self.key, self.derived_key.as_ref())? else {
+            self.file_hash = None;
+            return Ok(());
+        };
+        for (key, theirs) in file.records {
+            match self.data.get(&key) {
+                Some(ours) if ours.modified >= theirs.modified => {}
+                _ => {
+                    self.data.insert(key, theirs);
+                }
+            }
+        }
+        self.file_hash = Some(file.hash);
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn verify_password(&self, password: &str) -> bool {
+        self.key.password() == password
+    }
+
+    /// Re-encrypts the database with `new_password` (and a fresh salt).
+    /// The key file, if any, stays the same.
+    /// The previous file is kept as a backup, like on every save. Backups
+    /// stay encrypted with the password they were made with, so that
+    /// [`Db::verify_backup`] and [`Db::restore_backup`] fail on them with
+    /// [`Error::BackupKeyChanged`].
+    pub fn change_password(&mut self, new_password: &str) -> Result<(), Error> {
+        let new_key = self.key.with_password(new_password);
+        let old_key = std::mem::replace(&mut self.key, new_key);
+        let old_derived_key = self.derived_key.take();
+        let result = self.save();
+        if result.is_err() {
+            self.key = old_key;
+            self.derived_key = old_derived_key;
+        }
+        result
+    }
+
+    /// Re-encrypts the database with a fresh salt. Saves otherwise keep the
+    /// salt (and the key derived from it) until the password or the key
+    /// derivation function changes.
+    pub fn rotate_salt(&mut self) -> Result<(), Error> {
+        let old_derived_key = self
+            .derived_key
+            .replace(DerivedKey::new(&self.key, self.kdf)?);
+        let result = self.save();
+        if result.is_err() {
+            self.derived_key = old_derived_key;
+        }
+        result
+    }
+}
+
+type FileHash = [u8; 32];
+
+fn file_hash(path: &Path) -> io::Result<Option<FileHash>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(Sha256::digest(bytes).into())),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// `keys.db` as read from disk.
+struct LoadedFile {
+    records: BTreeMap<String, DbRecord>,
+    derived_key: DerivedKey,
+    hash: FileHash,
+}
+
+/// Reads and decrypts `path`; `None` if there is no such file. `cached_key`
+/// is used instead of running the KDF if the file was written with it.
+fn read_file(
+    path: &Path,
+    key: &CompositeKey,
+    cached_key: Option<&DerivedKey>,
+) -> Result<Option<LoadedFile>, Error> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let hash = Sha256::digest(&bytes).into();
+    let data = encrypted_file::parse_bytes(&bytes)?;
+    let derived_key = match cached_key {
+        Some(cached_key) if cached_key.is_for(&data) => cached_key.clone(),
+        _ => DerivedKey::for_file(&data, key)?,
+    };
+    let contents = encrypted_file::decrypt_with_key(&data, &derived_key)?;
+    let dto: Vec<DbRecordDTO> = serde_json::from_str(&contents)
+        .map_err(|e| Error::Corrupt(format!("invalid json: {e}")))?;
+    let mut records = BTreeMap::new();
+    for mut r in dto {
+        let record = r.take_record()?;
+        records.insert(record.key.clone(), record);
+    }
+    Ok(Some(LoadedFile {
+        records,
+        derived_key,
+        hash,
+    }))
+}
+
+impl Db {
+    /// The policy backups are pruned with after every save, if the user has
+    /// chosen one.
+    #[must_use]
+    pub fn retention_policy(&self) -> Option<RetentionPolicy> {
+        self.retention
+    }
+
+    /// Sets and stores the policy used to prune backups after every save;
+    /// with `None`, backups are no longer pruned.
+    pub fn set_retention_policy(&mut self, policy: Option<RetentionPolicy>) -> Result<(), Error> {
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        let path = get_db_path(PathKind::Retention, &self.location);
+        backup::write_policy(&path, policy.as_ref())?;
+        self.retention = policy;
+        Ok(())
+    }
+
+    /// Backups of this database, newest first.
+    pub fn backups(&self) -> Result<Vec<Backup>, Error> {
+        backup::list(&db_dir(&self.location))
+    }
+
+    /// Deletes the backups `policy` does not keep; returns them.
+    pub fn prune_backups(&self, policy: &RetentionPolicy) -> Result<Vec<Backup>, Error> {
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        backup::prune(&db_dir(&self.location), policy)
+    }
+
+    /// Checks that the backup decrypts with the current key and contains a
+    /// valid database; returns the number of records in it. Fails with
+    /// [`Error::BackupKeyChanged`] if the backup was made with another
+    /// password, see [`Db::change_password`].
+    pub fn verify_backup(&self, backup: &Backup) -> Result<usize, Error> {
+        Ok(self.read_backup(backup)?.records.len())
+    }
+
+    /// Replaces the records with the ones from the backup and saves. The
+    /// current file is backed up first, like on every save. Fails like
+    /// [`Db::verify_backup`] on backups made with another password.
+    pub fn restore_backup(&mut self, backup: &Backup) -> Result<(), Error> {
+        let restored = self.read_backup(backup)?.records;
+        let old_data = std::mem::replace(&mut self.data, restored);
+        let result = self.save();
+        if result.is_err() {
+            self.data = old_data;
+        }
+        result
+    }
+
+    fn read_backup(&self, backup: &Backup) -> Result<LoadedFile, Error> {
+        match read_file(backup.path(), &self.key, self.derived_key.as_ref()) {
+            Ok(Some(loaded)) => Ok(loaded),
+            Ok(None) => Err(io::Error::from(io::ErrorKind::NotFound).into()),
+            Err(Error::WrongPassword | Error::KeyFileRequired) => Err(Error::BackupKeyChanged),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn db_dir(location: &DbLocation) -> PathBuf {
+    let mut path = get_db_path(PathKind::Main, location);
+    path.pop();
+    path
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn password(password: &str) -> CompositeKey {
+        CompositeKey::new(password, None).expect("no key file to read")
+    }
+
     #[test]
     fn db_recorddto_is_serializable() {
         serde_json::to_string(&DbRecordDTO {
             key: String::new(),
-            timestamp: String::new(),
-            value: BTreeMap::new(),
+            created: None,
+            modified: None,
+            value_changed: None,
+            accessed: None,
+            timestamp: None,
+            value: IndexMap::new(),
+            fields: BTreeMap::new(),
+            history: Vec::new(),
+            tags: BTreeSet::new(),
+            attachments: BTreeMap::new(),
+            folder: None,
+            password_policy: None,
         })
         .expect("DbRecordDTO should be serializable");
     }
+
+    #[test]
+    fn change_password_reencrypts_and_keeps_backup() {
+        let dir = std::env::temp_dir().join(format!("cred-man-test-{}-passwd", std::process::id()));
+        let location = DbLocation::SpecifiedDirectory(dir.clone());
+        let mut db = Db::load(&location, &password("old")).expect("new db should be created");
+        db.data
+            .insert("k".to_string(), DbRecord::new("k".to_string()));
+        db.save().expect("save");
+        db.change_password("new").expect("change_password");
+        assert!(db.verify_password("new"));
+        drop(db);
+
+        assert!(matches!(
+            Db::load(&location, &password("old")),
+            Err(Error::WrongPassword)
+        ));
+        let db = Db::load(&location, &password("new")).expect("new password should be accepted");
+        assert!(db.data.contains_key("k"));
+        let backups = fs::read_dir(&dir)
+            .expect("read_dir")
+            .filter(|e| {
+                e.as_ref()
+                    .expect("dir entry")
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("keys.backup.")
+            })
+            .count();
+        assert_eq!(backups, 1);
+        let backup = &db.backups().expect("backups")[0];
+        assert!(matches!(
+            db.verify_backup(backup),
+            Err(Error::BackupKeyChanged)
+        ));
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn backups_are_pruned_only_with_a_stored_policy() {
+        let dir =
+            std::env::temp_dir().join(format!("cred-man-test-{}-retention", std::process::id()));
+        let location = DbLocation::SpecifiedDirectory(dir.clone());
+        let mut db = Db::load(&location, &password("pw")).expect("new db should be created");
+        db.save().expect("save");
+        fs::write(dir.join("keys.backup.20000101_000000.db"), b"old").expect("write");
+        db.save().expect("save");
+        assert_eq!(db.backups().expect("backups").len(), 2);
+
+        let policy = RetentionPolicy {
+            keep_last: 1,
+            keep_daily: 0,
+            keep_weekly: 0,
+        };
+        db.set_retention_policy(Some(policy))
+            .expect("set_retention_policy");
+        drop(db);
+        let mut db = Db::load(&location, &password("pw")).expect("load");
+        assert_eq!(db.retention_policy(), Some(policy));
+        db.save().expect("save");
+        assert_eq!(db.backups().expect("backups").len(), 1);
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn failed_pruning_does_not_fail_the_save() {
+        let dir = std::env::temp_dir().join(format!("cred-man-test-{}-prune", std::process::id()));
+        let location = DbLocation::SpecifiedDirectory(dir.clone());
+        let mut db = Db::load(&location, &password("old")).expect("new db should be created");
+        db.save().expect("save");
+        // A "backup" that can not be deleted.
+        fs::create_dir_all(dir.join("keys.backup.20000101_000000.db/x")).expect("create_dir_all");
+        db.set_retention_policy(Some(RetentionPolicy {
+            keep_last: 0,
+            keep_daily: 0,
+            keep_weekly: 0,
+        }))
+        .expect("set_retention_policy");
+        db.change_password("new").expect("change_password");
+        assert!(db.verify_password("new"));
+        assert!(db.take_prune_error().is_some());
+        drop(db);
+
+        Db::load(&location, &password("new")).expect("new password should be accepted");
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn second_writer_is_locked_out() {
+        let dir = std::env::temp_dir().join(format!("cred-man-test-{}-lock", std::process::id()));
+        let location = DbLocation::SpecifiedDirectory(dir.clone());
+        let mut db = Db::load(&location, &password("pw")).expect("new db should be created");
+        db.save().expect("save");
+
+        let Err(Error::Locked(holder)) = Db::load(&location, &password("pw")) else {
+            panic!("database should be locked");
+        };
+        assert!(holder.contains(&std::process::id().to_string()));
+        let mut read_only = Db::load_read_only(&location, &password("pw")).expect("load");
+        assert!(read_only.is_read_only());
+        assert!(matches!(read_only.save(), Err(Error::ReadOnly)));
+
+        drop(db);
+        Db::load(&location, &password("pw")).expect("lock should be released");
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn save_detects_external_changes() {
+        let dir =
+            std::env::temp_dir().join(format!("cred-man-test-{}-conflict", std::process::id()));
+        let location = DbLocation::SpecifiedDirectory(dir.clone());
+        let mut db = Db::load(&location, &password("pw")).expect("new db should be created");
+        db.save().expect("save");
+
+        let theirs = r#"[{"key":"theirs","timestamp":"2020-01-01T00:00:00","value":{}}]"#;
+        let data = encrypted_file::encrypt(theirs, &password("pw"), db.kdf).expect("encrypt");
+        encrypted_file::write_to_file(get_db_path(PathKind::Main, &location), &data)
+            .expect("write");
+        db.data
+            .insert("ours".to_string(), DbRecord::new("ours".to_string()));
+        assert!(matches!(db.save(), Err(Error::Conflict)));
+
+        db.merge().expect("merge");
+        assert!(db.data.contains_key("ours") && db.data.contains_key("theirs"));
+        db.save().expect("save after merge");
+        db.reload().expect("reload");
+        assert_eq!(db.data.len(), 2);
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn tags_and_folder_are_kept() {
+        let mut record = DbRecord::new("k".to_string());
+        assert!(record.add_tag("work"));
+        assert!(!record.add_tag("work"));
+        record.set_folder(" /servers// eu/ ");
+        assert_eq!(record.folder.as_deref(), Some("servers/eu"));
+
+        let json = serde_json::to_string(&DbRecordDTO::from_record(&record)).expect("serializable");
+        let mut parsed: DbRecordDTO = serde_json::from_str(&json).expect("deserializable");
+        let mut parsed = parsed.take_record().expect("valid");
+        assert_eq!(parsed.tags, record.tags);
+        assert_eq!(parsed.folder, record.folder);
+        parsed.set_folder("/");
+        assert!(parsed.folder.is_none());
+    }
+
+    #[test]
+    fn field_meta_follows_the_field() {
+        let mut record = DbRecord::new("k".to_string());
+        record.value.insert("name".to_string(), "me".to_string());
+        assert!(record.is_sensitive("name"));
+        record.set_field_meta("name", FieldMeta::of_kind(FieldKind::Username));
+        assert!(record.rename_field("name", "login"));
+        assert_eq!(record.field_meta("login").kind, FieldKind::Username);
+        assert!(!record.is_sensitive("login"));
+        assert_eq!(record.history.len(), 1);
+        assert!(record.history[0].value.contains_key("name"));
+        assert!(record.rename_field("login", "login"));
+        assert!(!record.rename_field("name", "login"));
+        assert_eq!(record.history.len(), 1);
+
+        let json = serde_json::to_string(&DbRecordDTO::from_record(&record)).expect("serializable");
+        let mut parsed: DbRecordDTO = serde_json::from_str(&json).expect("deserializable");
+        let mut parsed = parsed.take_record().expect("valid");
+        assert_eq!(parsed.fields, record.fields);
+        assert!(parsed.remove_field("login"));
+        assert!(parsed.fields.is_empty());
+        assert!(!parsed.remove_field("login"));
+        assert_eq!(parsed.history.len(), 2);
+        assert!(parsed.restore_revision(1));
+        assert_eq!(parsed.value["login"], "me");
+    }
+
+    #[test]
+    fn attachments_are_kept_and_limited() {
+        let dir = std::env::temp_dir().join(format!("cred-man-test-{}-attach", std::process::id()));
+        let location = DbLocation::SpecifiedDirectory(dir.clone());
+        let mut db = Db::load(&location, &password("pw")).expect("new db should be created");
+        db.data
+            .insert("k".to_string(), DbRecord::new("k".to_string()));
+        let data = (0..=255).collect::<Vec<u8>>();
+        assert!(db.attach("k", "cert.p12", data.clone()).expect("attach"));
+        assert!(!db
+            .attach("missing", "cert.p12", data.clone())
+            .expect("attach"));
+        assert!(matches!(
+            db.attach("k", "big", vec![0; attachment::MAX_ATTACHMENT_SIZE + 1]),
+            Err(Error::AttachmentTooLarge { .. })
+        ));
+        db.save().expect("save");
+        db.reload().expect("reload");
+        assert_eq!(db.data["k"].attachments["cert.p12"].data(), data);
+        fs::remove_dir_all(&dir).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn field_order_is_kept() {
+        let mut record = DbRecord::new("k".to_string());
+        for name in ["username", "password", "url"] {
+            record.value.insert(name.to_string(), String::new());
+        }
+        assert!(record.move_field("url", 0));
+        assert!(record.rename_field("password", "pin"));
+        assert!(!record.move_field("password", 0));
+
+        let json = serde_json::to_string(&DbRecordDTO::from_record(&record)).expect("serializable");
+        let mut parsed: DbRecordDTO = serde_json::from_str(&json).expect("deserializable");
+        let parsed = parsed.take_record().expect("valid");
+        assert_eq!(
+            parsed.value.keys().collect::<Vec<_>>(),
+            ["url", "username", "pin"]
+        );
+    }
+
+    #[test]
+    fn legacy_local_timestamps_are_migrated() {
+        let json = r#"{"key":"k","timestamp":"2020-06-01T12:00:00","value":{},
+            "history":[{"timestamp":"2020-05-01T12:00:00","value":{}}]}"#;
+        let mut dto: DbRecordDTO = serde_json::from_str(json).expect("deserializable");
+        let record = dto.take_record().expect("valid");
+        let expected = Local
+            .with_ymd_and_hms(2020, 6, 1, 12, 0, 0)
+            .single()
+            .expect("unambiguous")
+            .with_timezone(&Utc);
+        assert_eq!(record.created, expected);
+        assert_eq!(record.modified, expected);
+        assert_eq!(record.accessed, None);
+
+        let mut parsed: DbRecordDTO = serde_json::from_str(
+            &serde_json::to_string(&DbRecordDTO::from_record(&record)).expect("serializable"),
+        )
+        .expect("deserializable");
+        assert!(parsed.timestamp.is_none());
+        let reparsed = parsed.take_record().expect("valid");
+        assert_eq!(reparsed.modified, expected);
+        assert_eq!(reparsed.history[0].timestamp, record.history[0].timestamp);
+    }
+
+    #[test]
+    fn exports_keep_attachment_times() {
+        let json = r#"[{"key":"k","modified":"2020-06-01T12:00:00Z","value":{},
+            "attachments":{"a":"AQI=","b":{"added":"2020-05-01T12:00:00Z","data":"AwQ="}}}]"#;
+        let records = records_from_json(json).expect("valid");
+        let attachments = &records[0].attachments;
+        assert_eq!(attachments["a"].data(), [1, 2]);
+        assert_eq!(attachments["a"].added, records[0].modified);
+        assert_eq!(attachments["b"].data(), [3, 4]);
+
+        let reparsed = records_from_json(&records_to_json(&records)).expect("valid");
+        assert_eq!(
+            reparsed[0].attachments["b"].added,
+            Utc.with_ymd_and_hms(2020, 5, 1, 12, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn history_is_bounded_and_restorable() {
+        let mut record = DbRecord::new("k".to_string());
+        for i in 0..=HISTORY_LIMIT + 1 {
+            record.new_revision();
+            record.value.insert("password".to_string(), i.to_string());
+        }
+        assert_eq!(record.history.len(), HISTORY_LIMIT);
+        let value = |values: &IndexMap<String, String>| values["password"].clone();
+        assert_eq!(
+            value(&record.history[HISTORY_LIMIT - 1].value),
+            HISTORY_LIMIT.to_string()
+        );
+
+        assert!(record.restore_revision(HISTORY_LIMIT - 1));
+        assert_eq!(value(&record.value), HISTORY_LIMIT.to_string());
+        assert_eq!(
+            value(&record.history[HISTORY_LIMIT - 1].value),
+            (HISTORY_LIMIT + 1).to_string()
+        );
+        assert!(!record.restore_revision(HISTORY_LIMIT));
+
+        let dto = DbRecordDTO::from_record(&record);
+        let json = serde_json::to_string(&dto).expect("serializable");
+        let mut parsed: DbRecordDTO = serde_json::from_str(&json).expect("deserializable");
+        assert_eq!(
+            parsed.take_record().expect("valid").history.len(),
+            HISTORY_LIMIT
+        );
+    }
 }
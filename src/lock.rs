@@ -0,0 +1,78 @@
+//! Advisory lock that lets only one process at a time have a database
+//! open for writing.
+//!
+//! The lock is an `flock` on a file next to `keys.db`, so it goes away
+//! together with the process holding it. The holder writes its pid and name
+//! into the file, so that others can tell who it is.
+
+use crate::Error;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub(crate) struct DbLock {
+    /// Closing the file releases the lock.
+    _file: File,
+}
+
+impl DbLock {
+    /// Takes the lock without waiting for it. Fails with [`Error::Locked`]
+    /// if another process holds it.
+    pub(crate) fn acquire(path: &Path) -> Result<DbLock, Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if !try_lock(&file)? {
+            let mut holder = String::new();
+            // Best effort: the holder may not have written itself down yet.
+            let _ = file.read_to_string(&mut holder);
+            return Err(Error::Locked(describe_holder(holder.trim())));
+        }
+        file.set_len(0)?;
+        writeln!(file, "{} {}", std::process::id(), process_name())?;
+        Ok(DbLock { _file: file })
+    }
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: the descriptor belongs to `file`, which outlives the call.
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    if e.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(e)
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> io::Result<bool> {
+    Ok(true)
+}
+
+fn process_name() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn describe_holder(contents: &str) -> String {
+    match contents.split_once(' ') {
+        Some((pid, name)) => format!("process {pid} ({name})"),
+        None if !contents.is_empty() => format!("process {contents}"),
+        None => "another process".to_string(),
+    }
+}
@@ -0,0 +1,343 @@
+//! One-time codes for two-factor authentication: TOTP (RFC 6238) and HOTP
+//! (RFC 4226).
+//!
+//! A record holds the seed in a field of kind [`FieldKind::Otp`], either as
+//! an `otpauth://` URI (as encoded in the QR codes sites show) or as a bare
+//! base32 secret, which means TOTP with SHA-1, 6 digits and 30 seconds.
+
+use crate::{DbRecord, Error, FieldKind};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OtpKind {
+    /// Time-based; a new code every `period` seconds.
+    Totp { period: u64 },
+    /// Counter-based; the counter must be stored back after every code, see
+    /// [`next_code`].
+    Hotp { counter: u64 },
+}
+
+pub struct Otp {
+    secret: Zeroizing<Vec<u8>>,
+    pub algorithm: OtpAlgorithm,
+    pub digits: u32,
+    pub kind: OtpKind,
+}
+
+impl Otp {
+    /// The code for the given time (TOTP) or the current counter (HOTP).
+    #[must_use]
+    pub fn code_at(&self, unix_time: u64) -> String {
+        let counter = match self.kind {
+            OtpKind::Totp { period } => unix_time / period,
+            OtpKind::Hotp { counter } => counter,
+        };
+        self.hotp(counter)
+    }
+
+    /// The current code and, for TOTP, how many seconds it stays valid.
+    #[must_use]
+    pub fn now(&self) -> (String, Option<u64>) {
+        let unix_time = chrono::Utc::now().timestamp().unsigned_abs();
+        let remaining = match self.kind {
+            OtpKind::Totp { period } => Some(period - unix_time % period),
+            OtpKind::Hotp { .. } => None,
+        };
+        (self.code_at(unix_time), remaining)
+    }
+
+    fn hotp(&self, counter: u64) -> String {
+        let message = counter.to_be_bytes();
+        let hash = match self.algorithm {
+            OtpAlgorithm::Sha1 => hmac::<Hmac<Sha1>>(&self.secret, &message),
+            OtpAlgorithm::Sha256 => hmac::<Hmac<Sha256>>(&self.secret, &message),
+            OtpAlgorithm::Sha512 => hmac::<Hmac<Sha512>>(&self.secret, &message),
+        };
+        // Dynamic truncation, RFC 4226 section 5.3
+        let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        let code = u64::from(binary) % 10u64.pow(self.digits);
+        format!("{code:0width$}", width = self.digits as usize)
+    }
+}
+
+fn hmac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    Zeroizing::new(mac.finalize().into_bytes().to_vec())
+}
+
+impl FromStr for Otp {
+    type Err = Error;
+
+    /// Parses an `otpauth://` URI or a bare base32 secret.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let Some(rest) = s.strip_prefix("otpauth://") else {
+            return Ok(Otp {
+                secret: base32_decode(s)?,
+                algorithm: OtpAlgorithm::Sha1,
+                digits: 6,
+                kind: OtpKind::Totp { period: 30 },
+            });
+        };
+        let (kind, rest) = rest
+            .split_once('/')
+            .ok_or_else(|| Error::InvalidOtp("no label in otpauth URI".to_string()))?;
+        let query = rest.split_once('?').map_or("", |(_, query)| query);
+        let mut secret = None;
+        let mut algorithm = OtpAlgorithm::Sha1;
+        let mut digits = 6;
+        let mut period = 30;
+        let mut counter = None;
+        for (name, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+            let invalid = || Error::InvalidOtp(format!("invalid {name} in otpauth URI: {value}"));
+            match name.to_lowercase().as_str() {
+                "secret" => secret = Some(base32_decode(&percent_decode(value))?),
+                "algorithm" => {
+                    algorithm = match value.to_uppercase().as_str() {
+                        "SHA1" => OtpAlgorithm::Sha1,
+                        "SHA256" => OtpAlgorithm::Sha256,
+                        "SHA512" => OtpAlgorithm::Sha512,
+                        _ => return Err(invalid()),
+                    };
+                }
+                "digits" => {
+                    digits = u32::from_str(value)
+                        .ok()
+                        .filter(|d| (6..=10).contains(d))
+                        .ok_or_else(invalid)?;
+                }
+                "period" => {
+                    period = u64::from_str(value)
+                        .ok()
+                        .filter(|p| *p > 0)
+                        .ok_or_else(invalid)?;
+                }
+                "counter" => counter = Some(u64::from_str(value).map_err(|_| invalid())?),
+                // issuer, image, ...
+                _ => {}
+            }
+        }
+        let kind = match kind.to_lowercase().as_str() {
+            "totp" => OtpKind::Totp { period },
+            "hotp" => OtpKind::Hotp {
+                counter: counter.ok_or_else(|| {
+                    Error::InvalidOtp("no counter in otpauth://hotp URI".to_string())
+                })?,
+            },
+            _ => return Err(Error::InvalidOtp(format!("unknown otpauth type: {kind}"))),
+        };
+        Ok(Otp {
+            secret: secret
+                .ok_or_else(|| Error::InvalidOtp("no secret in otpauth URI".to_string()))?,
+            algorithm,
+            digits,
+            kind,
+        })
+    }
+}
+
+/// The first field of the record that holds a one-time code seed: one of
+/// kind [`FieldKind::Otp`] or an `otpauth://` URI. Returns its name and the
+/// parsed seed, or, if no such field can be parsed, why the first one could
+/// not.
+#[must_use]
+pub fn find_in(record: &DbRecord) -> Option<(&str, Result<Otp, Error>)> {
+    let mut candidates = record
+        .value
+        .iter()
+        .filter(|(name, value)| {
+            record.field_meta(name).kind == FieldKind::Otp || value.starts_with("otpauth://")
+        })
+        .map(|(name, value)| (name.as_str(), Otp::from_str(value)));
+    let first = candidates.next()?;
+    if first.1.is_ok() {
+        return Some(first);
+    }
+    Some(candidates.find(|(_, otp)| otp.is_ok()).unwrap_or(first))
+}
+
+/// The current code of the record's one-time code field (see [`find_in`])
+/// and, for TOTP, how many seconds it stays valid. An HOTP code is used up:
+/// the counter stored in the field is incremented, so the record has to be
+/// saved.
+pub fn next_code(record: &mut DbRecord) -> Option<Result<(String, Option<u64>), Error>> {
+    let (name, otp) = find_in(record)?;
+    let otp = match otp {
+        Ok(otp) => otp,
+        Err(e) => return Some(Err(e)),
+    };
+    let code = otp.now();
+    if let OtpKind::Hotp { counter } = otp.kind {
+        let Some(next) = counter.checked_add(1) else {
+            return Some(Err(Error::InvalidOtp(
+                "HOTP counter can't be incremented".to_string(),
+            )));
+        };
+        let name = name.to_string();
+        let value = record.value.get_mut(&name)?;
+        let mut updated = with_counter(value, next);
+        std::mem::swap(value, &mut *updated);
+        record.modified = chrono::Utc::now();
+    }
+    Some(Ok(code))
+}
+
+/// `uri` with the `counter` parameter set to `counter`.
+fn with_counter(uri: &str, counter: u64) -> Zeroizing<String> {
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    let counter = format!("counter={counter}");
+    let parameters = query
+        .split('&')
+        .filter(|p| !p.is_empty() && !p.to_lowercase().starts_with("counter="))
+        .chain([counter.as_str()])
+        .collect::<Vec<_>>();
+    Zeroizing::new(format!("{path}?{}", parameters.join("&")))
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32; case-insensitive, ignores spaces, dashes and padding.
+fn base32_decode(s: &str) -> Result<Zeroizing<Vec<u8>>, Error> {
+    let mut bytes = Zeroizing::new(Vec::with_capacity(s.len() * 5 / 8));
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for c in s.bytes().filter(|c| !matches!(c, b' ' | b'-' | b'=')) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())
+            .ok_or_else(|| Error::InvalidOtp("secret is not base32".to_string()))?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            // Only the low 8 bits are wanted.
+            #[allow(clippy::cast_possible_truncation)]
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    if bytes.is_empty() {
+        return Err(Error::InvalidOtp("empty secret".to_string()));
+    }
+    Ok(bytes)
+}
+
+/// Decodes `%XX` escapes; invalid ones are kept as they are.
+fn percent_decode(s: &str) -> Zeroizing<String> {
+    let mut bytes = Zeroizing::new(Vec::with_capacity(s.len()));
+    let mut rest = s.as_bytes();
+    while let Some((&c, tail)) = rest.split_first() {
+        let escaped = (c == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(byte) = escaped {
+            bytes.push(byte);
+            rest = &tail[2..];
+        } else {
+            bytes.push(c);
+            rest = tail;
+        }
+    }
+    Zeroizing::new(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Test vectors from RFC 6238, appendix B.
+    #[test]
+    fn totp_matches_rfc_6238() {
+        let otp = |secret: &[u8], algorithm| Otp {
+            secret: Zeroizing::new(secret.to_vec()),
+            algorithm,
+            digits: 8,
+            kind: OtpKind::Totp { period: 30 },
+        };
+        let sha1 = otp(b"12345678901234567890", OtpAlgorithm::Sha1);
+        let sha256 = otp(b"12345678901234567890123456789012", OtpAlgorithm::Sha256);
+        let sha512 = otp(
+            b"1234567890123456789012345678901234567890123456789012345678901234",
+            OtpAlgorithm::Sha512,
+        );
+        assert_eq!(sha1.code_at(59), "94287082");
+        assert_eq!(sha256.code_at(59), "46119246");
+        assert_eq!(sha512.code_at(59), "90693936");
+        assert_eq!(sha1.code_at(1_111_111_109), "07081804");
+        assert_eq!(sha1.code_at(20_000_000_000), "65353130");
+    }
+
+    /// Test vectors from RFC 4226, appendix D.
+    #[test]
+    fn hotp_matches_rfc_4226() {
+        let mut record = DbRecord::new("example".to_string());
+        record.value.insert(
+            "2fa".to_string(),
+            "otpauth://hotp/Example:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0&issuer=Example"
+                .to_string(),
+        );
+        let mut codes = Vec::new();
+        for _ in 0..3 {
+            let (code, remaining) = next_code(&mut record)
+                .expect("has a one-time code field")
+                .expect("valid URI");
+            assert_eq!(remaining, None);
+            codes.push(code);
+        }
+        assert_eq!(codes, ["755224", "287082", "359152"]);
+        assert_eq!(
+            record.value["2fa"],
+            "otpauth://hotp/Example:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Example&counter=3"
+        );
+
+        let last = format!(
+            "otpauth://hotp/x?secret=GEZDGNBVGY3TQOJQ&counter={}",
+            u64::MAX
+        );
+        record.value.insert("2fa".to_string(), last.clone());
+        assert!(matches!(
+            next_code(&mut record),
+            Some(Err(Error::InvalidOtp(_)))
+        ));
+        assert_eq!(record.value["2fa"], last);
+    }
+
+    #[test]
+    fn bare_secrets_and_uris_are_parsed() {
+        let bare = Otp::from_str("gezd gnbv gy3t qojq").expect("valid secret");
+        assert_eq!(bare.secret.as_slice(), b"1234567890");
+        assert_eq!(bare.kind, OtpKind::Totp { period: 30 });
+
+        let uri = Otp::from_str(
+            "otpauth://totp/ACME%20Co:john?secret=GEZDGNBVGY3TQOJQ&issuer=ACME%20Co&digits=8&period=60&algorithm=SHA256",
+        )
+        .expect("valid URI");
+        assert_eq!(uri.digits, 8);
+        assert_eq!(uri.algorithm, OtpAlgorithm::Sha256);
+        assert_eq!(uri.kind, OtpKind::Totp { period: 60 });
+        assert!(Otp::from_str("otpauth://totp/x?digits=6").is_err());
+        assert!(matches!(
+            Otp::from_str("not base32!"),
+            Err(Error::InvalidOtp(_))
+        ));
+    }
+}
@@ -0,0 +1,127 @@
+//! Keeping secrets (master password, derived keys, decrypted database)
+//! out of swap and core dumps.
+//!
+//! Everything here is best effort: failures to lock memory (e.g. because of
+//! `RLIMIT_MEMLOCK`) are ignored.
+
+use std::io;
+use std::ops::Deref;
+use zeroize::Zeroize;
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for String {}
+    impl Sealed for Vec<u8> {}
+}
+
+/// Heap-allocated buffer types that can be held in a [`Secret`].
+pub trait HeapBuffer: private::Sealed + Zeroize {
+    fn bytes(&self) -> &[u8];
+}
+
+impl HeapBuffer for String {
+    fn bytes(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl HeapBuffer for Vec<u8> {
+    fn bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Heap buffer that is locked in RAM and wiped when dropped.
+///
+/// Only shared access is given out, so the buffer is never reallocated and
+/// the locked region stays valid for the whole lifetime of the value.
+pub struct Secret<T: HeapBuffer> {
+    value: T,
+}
+
+pub type SecretString = Secret<String>;
+pub type SecretBytes = Secret<Vec<u8>>;
+
+impl<T: HeapBuffer> Secret<T> {
+    pub fn new(value: T) -> Self {
+        lock(value.bytes());
+        Secret { value }
+    }
+}
+
+impl<T: HeapBuffer> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: HeapBuffer + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Secret::new(self.value.clone())
+    }
+}
+
+impl<T: HeapBuffer> Drop for Secret<T> {
+    fn drop(&mut self) {
+        let bytes = self.value.bytes();
+        let (ptr, len) = (bytes.as_ptr(), bytes.len());
+        self.value.zeroize();
+        unlock(ptr, len);
+    }
+}
+
+#[cfg(unix)]
+fn lock(bytes: &[u8]) {
+    if !bytes.is_empty() {
+        // SAFETY: the range is a live allocation; mlock only changes the
+        // paging state of the pages it spans and does not touch the contents.
+        unsafe {
+            libc::mlock(bytes.as_ptr().cast(), bytes.len());
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unlock(ptr: *const u8, len: usize) {
+    if len != 0 {
+        // SAFETY: the range was passed to mlock by `lock` and the allocation
+        // is still alive; munlock does not touch the contents.
+        unsafe {
+            libc::munlock(ptr.cast(), len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_bytes: &[u8]) {}
+
+#[cfg(not(unix))]
+fn unlock(_ptr: *const u8, _len: usize) {}
+
+/// Prevents the process from writing core dumps (and, on Linux, from being
+/// attached to with ptrace by other processes of the same user).
+///
+/// Frontends call this before the master password is entered.
+pub fn disable_core_dumps() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: `limit` is a valid rlimit that outlives the call.
+        if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &raw const limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: PR_SET_DUMPABLE takes a single integer argument.
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
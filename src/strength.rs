@@ -0,0 +1,570 @@
+//! How hard a password is to guess, in the spirit of zxcvbn.
+//!
+//! The password is split into the cheapest sequence of patterns an attacker
+//! would try: common passwords, dictionary words (also reversed, capitalized
+//! or with digits for letters), the site's own name and username, keyboard
+//! rows, sequences, repeats and years; whatever is left is brute-forced. The
+//! guesses for the patterns are multiplied, and the total gives the score.
+
+use crate::generator::Wordlist;
+use crate::DbRecord;
+use chrono::Datelike;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+const COMMON_PASSWORDS: &str = include_str!("wordlists/common_passwords.txt");
+/// Rank given to the words of the bundled wordlists; they are common, but
+/// an attacker's dictionary has many more.
+const WORD_RANK: usize = 10_000;
+/// Only this many characters are matched against patterns; the rest count
+/// as brute force.
+const MAX_MATCHED_LENGTH: usize = 100;
+const KEYBOARD_ROWS: [&str; 5] = [
+    "`1234567890-=",
+    "~!@#$%^&*()_+",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+];
+/// Keys on a keyboard, for the guesses of keyboard patterns.
+const KEYBOARD_KEYS: f64 = 47.0;
+const LEET: [(char, char); 14] = [
+    ('4', 'a'),
+    ('@', 'a'),
+    ('8', 'b'),
+    ('(', 'c'),
+    ('3', 'e'),
+    ('6', 'g'),
+    ('1', 'i'),
+    ('!', 'i'),
+    ('0', 'o'),
+    ('$', 's'),
+    ('5', 's'),
+    ('7', 't'),
+    ('+', 't'),
+    ('2', 'z'),
+];
+
+pub struct Strength {
+    /// 0 (guessed right away) to 4 (out of reach)
+    pub score: u8,
+    /// Estimated number of guesses, as a power of ten
+    pub guesses_log10: f64,
+    /// What makes the password weak, for scores up to 2
+    pub warning: Option<&'static str>,
+    /// How to make it stronger, for scores up to 2
+    pub suggestions: Vec<&'static str>,
+}
+
+impl Strength {
+    #[must_use]
+    pub fn label(&self) -> &'static str {
+        ["very weak", "weak", "fair", "good", "strong"][usize::from(self.score)]
+    }
+}
+
+/// Score and label, e.g. `1/4 (weak)`.
+impl fmt::Display for Strength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/4 ({})", self.score, self.label())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pattern {
+    CommonPassword,
+    Word,
+    UserInput,
+    Keyboard,
+    Sequence,
+    Repeat,
+    Year,
+    BruteForce,
+}
+
+/// Rank of each entry of a dictionary, the most common first.
+type Ranks = HashMap<&'static str, usize>;
+/// Guesses for the cheapest matches up to a position, as a power of ten,
+/// and the last of these matches.
+type Cell = Option<(f64, Option<Match>)>;
+
+#[derive(Clone, Copy)]
+struct Match {
+    start: usize,
+    end: usize,
+    guesses_log10: f64,
+    pattern: Pattern,
+    reversed: bool,
+    leet: bool,
+    capitalized: bool,
+}
+
+impl Match {
+    fn new(start: usize, end: usize, guesses: f64, pattern: Pattern) -> Match {
+        // Every pattern takes some guesses to try, however short.
+        let min_guesses: f64 = if end - start == 1 { 10.0 } else { 50.0 };
+        Match {
+            start,
+            end,
+            guesses_log10: guesses.max(min_guesses).log10(),
+            pattern,
+            reversed: false,
+            leet: false,
+            capitalized: false,
+        }
+    }
+}
+
+/// Estimates the strength of `password`; `user_inputs` are words an
+/// attacker would try first, like the name of the site and the username.
+#[must_use]
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let chars = password.chars().collect::<Vec<_>>();
+    let matched = &chars[..chars.len().min(MAX_MATCHED_LENGTH)];
+    let mut ranks = HashMap::new();
+    for input in user_inputs {
+        let input = input.to_lowercase();
+        if input.chars().count() >= 3 {
+            let rank = ranks.len() + 1;
+            ranks.entry(input).or_insert(rank);
+        }
+    }
+    let user_inputs = ranks;
+    let (mut guesses_log10, sequence) = cheapest_sequence(matched, &user_inputs, true);
+    guesses_log10 += (chars.len() - matched.len()) as f64;
+    let score = match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+    let (warning, suggestions) = if score > 2 {
+        (None, Vec::new())
+    } else {
+        feedback(&sequence, chars.len())
+    };
+    Strength {
+        score,
+        guesses_log10,
+        warning,
+        suggestions,
+    }
+}
+
+/// Words from `record` that an attacker would try first for the password
+/// in its field `name`: the key of the record and the fields that aren't
+/// secret (the username, the URL), also split into parts.
+#[must_use]
+pub fn user_inputs<'a>(record: &'a DbRecord, name: &str) -> Vec<&'a str> {
+    let values = std::iter::once(record.key.as_str()).chain(
+        record
+            .value
+            .iter()
+            .filter(|(field, _)| *field != name && !record.is_sensitive(field))
+            .map(|(_, value)| value.as_str()),
+    );
+    // Also the parts of `user@example.com` or `https://example.com/login`.
+    values
+        .flat_map(|value| std::iter::once(value).chain(value.split(|c: char| !c.is_alphanumeric())))
+        .collect()
+}
+
+/// Estimates the strength of `password` for the field `name` of `record`,
+/// see [`user_inputs`].
+#[must_use]
+pub fn estimate_in(record: &DbRecord, name: &str, password: &str) -> Strength {
+    estimate(password, &user_inputs(record, name))
+}
+
+/// The sequence of matches covering `chars` that takes the fewest guesses,
+/// and that number as a power of ten.
+fn cheapest_sequence(
+    chars: &[char],
+    user_inputs: &HashMap<String, usize>,
+    with_repeats: bool,
+) -> (f64, Vec<Match>) {
+    let n = chars.len();
+    if n == 0 {
+        return (0.0, Vec::new());
+    }
+    let lower = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect::<Vec<_>>();
+    let mut by_end = vec![Vec::new(); n + 1];
+    let mut add = |m: Match| by_end[m.end].push(m);
+    for start in 0..n {
+        for end in start + 1..=n {
+            add(Match::new(
+                start,
+                end,
+                10f64.powi(i32::try_from(end - start).unwrap_or(i32::MAX)),
+                Pattern::BruteForce,
+            ));
+        }
+    }
+    dictionary_matches(chars, &lower, user_inputs, &mut add);
+    keyboard_matches(&lower, &mut add);
+    sequence_matches(&lower, &mut add);
+    year_matches(&lower, &mut add);
+    if with_repeats {
+        repeat_matches(chars, &lower, user_inputs, &mut add);
+    }
+
+    // best[end][count]: the cheapest `count` matches covering `..end`. The
+    // order of the matches has to be guessed too, hence the `count!`.
+    let mut best: Vec<Vec<Cell>> = vec![vec![None; n + 1]; n + 1];
+    best[0][0] = Some((0.0, None));
+    for end in 1..=n {
+        for m in &by_end[end] {
+            for count in 1..=m.start + 1 {
+                let Some((before, _)) = best[m.start][count - 1] else {
+                    continue;
+                };
+                let total = before + m.guesses_log10;
+                if best[end][count].is_none_or(|(current, _)| total < current) {
+                    best[end][count] = Some((total, Some(*m)));
+                }
+            }
+        }
+    }
+    let mut factorial_log10 = 0.0;
+    let mut cheapest: Option<(f64, usize)> = None;
+    for (count, cell) in best[n].iter().enumerate().skip(1) {
+        factorial_log10 += (count as f64).log10();
+        if let Some((guesses, _)) = *cell {
+            let total = guesses + factorial_log10;
+            if cheapest.is_none_or(|(current, _)| total < current) {
+                cheapest = Some((total, count));
+            }
+        }
+    }
+    let (total, mut count) = cheapest.expect("brute force covers everything");
+    let mut sequence = Vec::with_capacity(count);
+    let mut end = n;
+    while let Some((_, Some(m))) = best[end][count] {
+        sequence.push(m);
+        end = m.start;
+        count -= 1;
+    }
+    sequence.reverse();
+    (total, sequence)
+}
+
+/// Common passwords and words.
+fn dictionaries() -> &'static (Ranks, Ranks) {
+    static DICTIONARIES: OnceLock<(Ranks, Ranks)> = OnceLock::new();
+    DICTIONARIES.get_or_init(|| {
+        let common = COMMON_PASSWORDS
+            .lines()
+            .enumerate()
+            .map(|(rank, password)| (password, rank + 1))
+            .collect();
+        let words = [Wordlist::Short, Wordlist::Long]
+            .into_iter()
+            .flat_map(Wordlist::words)
+            .map(|word| (word, WORD_RANK))
+            .collect();
+        (common, words)
+    })
+}
+
+fn dictionary_matches(
+    chars: &[char],
+    lower: &[char],
+    user_inputs: &HashMap<String, usize>,
+    add: &mut impl FnMut(Match),
+) {
+    let (common, words) = dictionaries();
+    let lookup = |token: &str| -> Option<(usize, Pattern)> {
+        let found = [
+            (user_inputs.get(token).copied(), Pattern::UserInput),
+            (common.get(token).copied(), Pattern::CommonPassword),
+            (words.get(token).copied(), Pattern::Word),
+        ];
+        found
+            .into_iter()
+            .filter_map(|(rank, pattern)| Some((rank?, pattern)))
+            .min_by_key(|(rank, _)| *rank)
+    };
+    let n = lower.len();
+    for start in 0..n {
+        for end in start + 3..=n {
+            let token = &lower[start..end];
+            let variations = uppercase_variations(&chars[start..end]);
+            let plain = token.iter().collect::<String>();
+            let reversed = token.iter().rev().collect::<String>();
+            let candidates = [
+                (plain.clone(), false, 1.0),
+                (reversed, true, 2.0),
+                (unleet(token, 'i'), false, leet_variations(token)),
+                (unleet(token, 'l'), false, leet_variations(token)),
+            ];
+            for (candidate, is_reversed, factor) in candidates {
+                if let Some((rank, pattern)) = lookup(&candidate) {
+                    let mut m = Match::new(start, end, rank as f64 * variations * factor, pattern);
+                    m.reversed = is_reversed;
+                    m.leet = candidate != plain && !is_reversed;
+                    m.capitalized = variations > 1.0;
+                    add(m);
+                }
+            }
+        }
+    }
+}
+
+/// `token` with digits and symbols that stand for letters replaced by them;
+/// `one` is the letter taken for `1`.
+fn unleet(token: &[char], one: char) -> String {
+    token
+        .iter()
+        .map(|c| match c {
+            '1' => one,
+            c => LEET
+                .iter()
+                .find(|(leet, _)| leet == c)
+                .map_or(*c, |(_, letter)| *letter),
+        })
+        .collect()
+}
+
+fn leet_variations(token: &[char]) -> f64 {
+    let substituted = token
+        .iter()
+        .filter(|c| LEET.iter().any(|(leet, _)| leet == *c))
+        .count();
+    2f64.powi(i32::try_from(substituted).unwrap_or(i32::MAX))
+}
+
+/// How many ways of capitalizing the word an attacker has to try to get
+/// this one; the usual ones (first letter, all letters) are tried first.
+fn uppercase_variations(word: &[char]) -> f64 {
+    let upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let lower = word.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 1.0;
+    }
+    let first_only = upper == 1 && word[0].is_uppercase();
+    let last_only = upper == 1 && word[word.len() - 1].is_uppercase();
+    if lower == 0 || first_only || last_only {
+        return 2.0;
+    }
+    (1..=upper.min(lower))
+        .map(|k| binomial(upper + lower, k))
+        .sum()
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (1..=k).fold(1.0, |acc, i| acc * (n + 1 - i) as f64 / i as f64)
+}
+
+/// Runs of at least 4 neighboring keys of a keyboard row, either way.
+fn keyboard_matches(lower: &[char], add: &mut impl FnMut(Match)) {
+    let n = lower.len();
+    for start in 0..n {
+        for end in start + 4..=n {
+            let token = lower[start..end].iter().collect::<String>();
+            let reversed = token.chars().rev().collect::<String>();
+            for row in KEYBOARD_ROWS {
+                let is_reversed = row.contains(&reversed);
+                if row.contains(&token) || is_reversed {
+                    let direction = if is_reversed { 2.0 } else { 1.0 };
+                    let mut m = Match::new(
+                        start,
+                        end,
+                        KEYBOARD_KEYS * (end - start) as f64 * direction,
+                        Pattern::Keyboard,
+                    );
+                    m.reversed = is_reversed;
+                    add(m);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Runs of at least 3 letters or digits going up or down by one, like
+/// `abc` or `6543`.
+fn sequence_matches(lower: &[char], add: &mut impl FnMut(Match)) {
+    let n = lower.len();
+    let mut start = 0;
+    while start + 2 < n {
+        let step = |i: usize| {
+            let (a, b) = (lower[i], lower[i + 1]);
+            let same_class = (a.is_ascii_lowercase() && b.is_ascii_lowercase())
+                || (a.is_ascii_digit() && b.is_ascii_digit());
+            (same_class).then(|| i32::from(b as u8) - i32::from(a as u8))
+        };
+        let Some(delta @ (-1 | 1)) = step(start) else {
+            start += 1;
+            continue;
+        };
+        let mut end = start + 2;
+        while end < n && step(end - 1) == Some(delta) {
+            end += 1;
+        }
+        if end - start >= 3 {
+            let first = lower[start];
+            let base = if "az019".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let direction = if delta < 0 { 2.0 } else { 1.0 };
+            add(Match::new(
+                start,
+                end,
+                base * (end - start) as f64 * direction,
+                Pattern::Sequence,
+            ));
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+/// Years from 1900 to 2099; recent ones are the likely ones.
+fn year_matches(lower: &[char], add: &mut impl FnMut(Match)) {
+    let this_year = chrono::Utc::now().year();
+    for start in 0..lower.len().saturating_sub(3) {
+        let token = lower[start..start + 4].iter().collect::<String>();
+        if let Ok(year) = token.parse::<i32>() {
+            if (1900..2100).contains(&year) {
+                let distance = f64::from((year - this_year).abs()).max(20.0);
+                add(Match::new(start, start + 4, distance, Pattern::Year));
+            }
+        }
+    }
+}
+
+/// The same characters repeated, like `aaa` or `abcabc`; the repeated part
+/// is estimated on its own.
+fn repeat_matches(
+    chars: &[char],
+    lower: &[char],
+    user_inputs: &HashMap<String, usize>,
+    add: &mut impl FnMut(Match),
+) {
+    let n = lower.len();
+    // Units repeated in several places are estimated once.
+    let mut unit_guesses_log10 = HashMap::new();
+    for start in 0..n {
+        for unit in 1..=(n - start) / 2 {
+            let pattern = &lower[start..start + unit];
+            // Only the longest run of repeats, from its first occurrence.
+            if start >= unit && &lower[start - unit..start] == pattern {
+                continue;
+            }
+            let mut count = 1;
+            while start + (count + 1) * unit <= n
+                && &lower[start + count * unit..start + (count + 1) * unit] == pattern
+            {
+                count += 1;
+            }
+            if count >= 2 && count * unit >= 3 {
+                let unit_chars = &chars[start..start + unit];
+                let guesses_log10 = *unit_guesses_log10
+                    .entry(unit_chars)
+                    .or_insert_with(|| cheapest_sequence(unit_chars, user_inputs, false).0);
+                add(Match::new(
+                    start,
+                    start + count * unit,
+                    10f64.powf(guesses_log10) * count as f64,
+                    Pattern::Repeat,
+                ));
+            }
+        }
+    }
+}
+
+fn feedback(sequence: &[Match], length: usize) -> (Option<&'static str>, Vec<&'static str>) {
+    let Some(longest) = sequence
+        .iter()
+        .filter(|m| m.pattern != Pattern::BruteForce)
+        .max_by_key(|m| m.end - m.start)
+    else {
+        let suggestion = if length == 0 {
+            "Use a few words, avoid common phrases"
+        } else {
+            "Add another word or two. Uncommon words are better."
+        };
+        return (None, vec![suggestion]);
+    };
+    let whole = longest.end - longest.start == length;
+    let mut suggestions = vec!["Add another word or two. Uncommon words are better."];
+    let warning = match longest.pattern {
+        Pattern::CommonPassword if whole => Some("This is a very common password"),
+        Pattern::CommonPassword => Some("This is similar to a commonly used password"),
+        Pattern::Word if whole => Some("A word by itself is easy to guess"),
+        Pattern::Word | Pattern::BruteForce => None,
+        Pattern::UserInput => Some("The name of the site or the username is easy to guess"),
+        Pattern::Keyboard => {
+            suggestions.push("Use a longer keyboard pattern with more turns");
+            Some("Straight rows of keys are easy to guess")
+        }
+        Pattern::Sequence => {
+            suggestions.push("Avoid sequences");
+            Some("Sequences like abc or 6543 are easy to guess")
+        }
+        Pattern::Repeat => {
+            suggestions.push("Avoid repeated words and characters");
+            Some("Repeats like \"aaa\" or \"abcabc\" are easy to guess")
+        }
+        Pattern::Year => {
+            suggestions.push("Avoid recent years and dates associated with you");
+            Some("Recent years are easy to guess")
+        }
+    };
+    if longest.capitalized {
+        suggestions.push("Capitalization doesn't help very much");
+    }
+    if longest.reversed && longest.pattern != Pattern::Keyboard {
+        suggestions.push("Reversed words aren't much harder to guess");
+    }
+    if longest.leet {
+        suggestions.push("Predictable substitutions like '@' instead of 'a' don't help very much");
+    }
+    (warning, suggestions)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn patterns_make_passwords_weak() {
+        let weak = [
+            "password",
+            "P@ssw0rd",
+            "qwerty123",
+            "abcdefgh",
+            "zzzzzzzzzz",
+            "1987",
+            "drowssap",
+        ];
+        for password in weak {
+            let strength = estimate(password, &[]);
+            assert!(strength.score <= 1, "{password}: {strength}");
+            assert!(strength.warning.is_some(), "{password}: no warning");
+        }
+        assert_eq!(
+            estimate("example.com", &["example.com"]).warning,
+            Some("The name of the site or the username is easy to guess")
+        );
+        assert_eq!(estimate("correct-horse-battery-staple", &[]).score, 4);
+        assert_eq!(estimate("tV8#qL2!xR", &[]).score, 4);
+        assert_eq!(estimate("", &[]).score, 0);
+
+        let mut record = DbRecord::new("mybank".to_string());
+        record
+            .value
+            .insert("username".to_string(), "jdoe@example.com".to_string());
+        assert!(estimate_in(&record, "password", "jdoe-mybank").score <= 1);
+    }
+}
@@ -1,31 +1,49 @@
 use cli_clipboard::{ClipboardContext, ClipboardProvider};
-use cred_man_lib::{Db, DbLoadResult, DbLocation};
+use std::path::{Path, PathBuf};
+
+use cred_man_lib::{CompositeKey, Db, DbLocation, Error, KdfAlgorithm};
 
 pub(crate) struct AppState {
     db_location: DbLocation,
+    kdf: Option<KdfAlgorithm>,
+    key_file: Option<PathBuf>,
     db: Option<Db>,
+    /// Why the database was opened read-only
+    read_only_reason: Option<String>,
     clipboard: Option<ClipboardContext>,
 }
 
 impl AppState {
-    pub(crate) fn new(db_location: DbLocation) -> Self {
+    pub(crate) fn new(
+        db_location: DbLocation,
+        kdf: Option<KdfAlgorithm>,
+        key_file: Option<PathBuf>,
+    ) -> Self {
         Self {
             db_location,
+            kdf,
+            key_file,
             db: None,
+            read_only_reason: None,
             clipboard: ClipboardContext::new().ok(),
         }
     }
 
-    pub(crate) fn view(&mut self) -> AppStateView {
+    #[allow(clippy::unnecessary_unwrap)]
+    pub(crate) fn view(&mut self) -> AppStateView<'_> {
         if self.db.is_some() {
             AppStateView::Opened(AppStateOpened {
                 db: self.db.as_mut().expect("db.is_some()"),
+                read_only_reason: self.read_only_reason.as_deref(),
                 clipboard: &mut self.clipboard,
             })
         } else {
             AppStateView::NotOpened(AppStateNotOpened {
                 db_location: &self.db_location,
+                kdf: self.kdf,
+                key_file: self.key_file.as_deref(),
                 db: &mut self.db,
+                read_only_reason: &mut self.read_only_reason,
             })
         }
     }
@@ -53,23 +71,38 @@ impl<'a> AppStateView<'a> {
 
 pub(crate) struct AppStateNotOpened<'a> {
     db_location: &'a DbLocation,
+    kdf: Option<KdfAlgorithm>,
+    key_file: Option<&'a Path>,
     db: &'a mut Option<Db>,
+    read_only_reason: &'a mut Option<String>,
 }
 
 impl AppStateNotOpened<'_> {
-    pub(crate) fn open(&mut self, password: &str) -> std::io::Result<bool> {
-        match Db::load(self.db_location, password)? {
-            DbLoadResult::Loaded(db) => {
-                *self.db = Some(db);
-                Ok(true)
+    /// Whether opening creates a new database, with the password given.
+    pub(crate) fn is_new(&self) -> bool {
+        !self.db_location.exists()
+    }
+
+    pub(crate) fn open(&mut self, password: &str) -> Result<(), Error> {
+        let key = CompositeKey::new(password, self.key_file)?;
+        let mut db = match Db::load(self.db_location, &key) {
+            Err(e @ Error::Locked(_)) => {
+                *self.read_only_reason = Some(e.to_string());
+                Db::load_read_only(self.db_location, &key)?
             }
-            DbLoadResult::WrongPassword => Ok(false),
+            result => result?,
+        };
+        if let Some(kdf) = self.kdf {
+            db.set_kdf(kdf);
         }
+        *self.db = Some(db);
+        Ok(())
     }
 }
 
 pub(crate) struct AppStateOpened<'a> {
     pub(crate) db: &'a mut Db,
+    pub(crate) read_only_reason: Option<&'a str>,
     pub(crate) clipboard: &'a mut Option<ClipboardContext>,
 }
 
@@ -25,15 +25,23 @@ use std::path::PathBuf;
 
 use app_state::AppState;
 use clap::Parser;
-use cred_man_lib::DbLocation;
+use cred_man_lib::{secret, DbLocation, KdfAlgorithm};
 
 mod app_state;
 mod ui;
 
 fn main() -> anyhow::Result<()> {
     let args = CliArgs::parse();
-    let mut app_state = AppState::new(args.db_location());
+    if let Err(e) = secret::disable_core_dumps() {
+        eprintln!("warning: unable to disable core dumps: {e}");
+    }
+    let mut app_state = AppState::new(args.db_location(), args.kdf, args.key_file.clone());
     ui::ui_main(&mut app_state)?;
+    if let Some(opened) = app_state.view().into_opened() {
+        if let Err(e) = opened.db.save_access_times() {
+            eprintln!("warning: access times are not saved: {e}");
+        }
+    }
     Ok(())
 }
 
@@ -41,6 +49,12 @@ fn main() -> anyhow::Result<()> {
 struct CliArgs {
     /// Path to the database
     db_path: Option<PathBuf>,
+    /// Key derivation function to use when saving (scrypt or argon2id)
+    #[arg(long)]
+    kdf: Option<KdfAlgorithm>,
+    /// Key file used together with the password
+    #[arg(long)]
+    key_file: Option<PathBuf>,
 }
 
 impl CliArgs {
@@ -1,13 +1,22 @@
+use std::time::Duration;
+
 use anyhow::Context;
+use cred_man_lib::strength::Strength;
 use login_view::LoginView;
 use main_view::MainView;
 use ratatui::{
     crossterm::event::{self, Event, KeyCode, KeyModifiers},
+    style::{Color, Style},
+    text::Span,
     DefaultTerminal, Frame,
 };
 
 use crate::app_state::AppState;
 
+mod audit_view;
+mod change_password_view;
+mod conflict_view;
+mod history_view;
 mod key_name_edit_view;
 mod login_view;
 mod main_view;
@@ -48,6 +57,10 @@ fn run(
             })
             .context("draw on terminal")?;
 
+        // Redraw at least every second for the one-time code countdown.
+        if !event::poll(Duration::from_secs(1)).context("poll ui events")? {
+            continue;
+        }
         let event = event::read().context("read ui event")?;
         match app_view.handle_event(app_state, &event)? {
             EventHandleResult::Continue => {}
@@ -89,3 +102,28 @@ impl AppView {
         }
     }
 }
+
+/// The first line of a value that may span several lines, for lists that
+/// show one value per line.
+fn first_line(value: &str) -> String {
+    let mut lines = value.lines();
+    let first = lines.next().unwrap_or_default();
+    match lines.count() {
+        0 => first.to_string(),
+        more => format!("{first} (+{more} lines)"),
+    }
+}
+
+/// The score of a password being typed, with the most useful hint.
+fn strength_span(strength: &Strength) -> Span<'static> {
+    let color = match strength.score {
+        0 | 1 => Color::Red,
+        2 => Color::Yellow,
+        _ => Color::Green,
+    };
+    let text = match strength.warning.or(strength.suggestions.first().copied()) {
+        Some(hint) => format!("strength: {strength}: {hint}"),
+        None => format!("strength: {strength}"),
+    };
+    Span::styled(text, Style::default().fg(color))
+}
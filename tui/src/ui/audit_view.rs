@@ -0,0 +1,189 @@
+use cred_man_lib::audit::{self, Report};
+use cred_man_lib::DbRecord;
+use ratatui::{
+    crossterm::event::{Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState},
+    Frame,
+};
+use std::collections::BTreeMap;
+
+/// Weak, reused and old passwords and records without a one-time code,
+/// see [`audit`].
+pub(crate) struct AuditView {
+    summary: String,
+    /// Section headings (without a key) and the records under them
+    lines: Vec<(Option<String>, String)>,
+    list_state: ListState,
+}
+
+pub(crate) enum EditResult {
+    Cancel,
+    /// Show the record
+    Select {
+        key_name: String,
+    },
+}
+
+impl AuditView {
+    pub(crate) fn new(data: &BTreeMap<String, DbRecord>) -> Self {
+        let report = audit::audit(data, audit::DEFAULT_MAX_AGE_DAYS);
+        let summary = format!(
+            "{} passwords in {} records",
+            report.passwords, report.records
+        );
+        let lines = Self::lines(&report);
+        let selected = lines.iter().position(|(key, _)| key.is_some());
+        Self {
+            summary,
+            lines,
+            list_state: ListState::default().with_selected(selected),
+        }
+    }
+
+    fn lines(report: &Report) -> Vec<(Option<String>, String)> {
+        let mut lines = Vec::new();
+        if !report.weak.is_empty() {
+            lines.push((None, "weak passwords".to_string()));
+            for weak in &report.weak {
+                let line = match weak.warning {
+                    Some(warning) => {
+                        format!("{}/{} ({}): {warning}", weak.key, weak.field, weak.label)
+                    }
+                    None => format!("{}/{} ({})", weak.key, weak.field, weak.label),
+                };
+                lines.push((Some(weak.key.clone()), line));
+            }
+        }
+        if !report.reused.is_empty() {
+            lines.push((None, "reused passwords".to_string()));
+            for fields in &report.reused {
+                let line = fields
+                    .iter()
+                    .map(|f| format!("{}/{}", f.key, f.field))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                lines.push((Some(fields[0].key.clone()), line));
+            }
+        }
+        if !report.old.is_empty() {
+            lines.push((
+                None,
+                format!(
+                    "not changed for more than {} days",
+                    audit::DEFAULT_MAX_AGE_DAYS
+                ),
+            ));
+            for old in &report.old {
+                lines.push((
+                    Some(old.key.clone()),
+                    format!("{} ({} days)", old.key, old.days),
+                ));
+            }
+        }
+        if !report.missing_two_factor.is_empty() {
+            lines.push((None, "no one-time code".to_string()));
+            for key in &report.missing_two_factor {
+                lines.push((Some(key.clone()), key.clone()));
+            }
+        }
+        lines
+    }
+
+    pub(crate) fn draw(&mut self, frame: &mut Frame<'_>) {
+        let [_, v_area, _] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Fill(1),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+        let [_, area, _] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Percentage(80),
+            Constraint::Fill(1),
+        ])
+        .areas(v_area);
+        let block = Block::new()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Green))
+            .title(format!("audit: {}", self.summary));
+        let [list_area, help_area] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(block.inner(area));
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
+
+        if self.lines.is_empty() {
+            frame.render_widget(Line::from("nothing to report"), list_area);
+        } else {
+            let items = self.lines.iter().map(|(key, line)| match key {
+                Some(_) => ListItem::new(format!("  {line}")),
+                None => ListItem::new(line.as_str())
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            });
+            let list = List::new(items)
+                .highlight_style(Style::new().bg(Color::Green).fg(Color::Black))
+                .highlight_symbol(">");
+            frame.render_stateful_widget(list, list_area, &mut self.list_state);
+        }
+
+        let help_message = Line::default().spans([Span::styled(
+            "<Enter> - show record <Esc> - close",
+            Style::default(),
+        )]);
+        frame.render_widget(help_message, help_area);
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &Event) -> Option<EditResult> {
+        let Event::Key(key_event) = event else {
+            return None;
+        };
+        if key_event.kind != KeyEventKind::Press {
+            return None;
+        }
+
+        match key_event.code {
+            KeyCode::Enter => {
+                if let Some((Some(key_name), _)) = self
+                    .list_state
+                    .selected()
+                    .and_then(|selected| self.lines.get(selected))
+                {
+                    return Some(EditResult::Select {
+                        key_name: key_name.clone(),
+                    });
+                }
+            }
+            KeyCode::Esc => {
+                return Some(EditResult::Cancel);
+            }
+            KeyCode::Up => {
+                self.move_selection(-1);
+            }
+            KeyCode::Down => {
+                self.move_selection(1);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Selects the next record up or down, skipping section headings.
+    fn move_selection(&mut self, step: isize) {
+        let Some(mut idx) = self.list_state.selected() else {
+            return;
+        };
+        while let Some(next) = idx
+            .checked_add_signed(step)
+            .filter(|&i| i < self.lines.len())
+        {
+            idx = next;
+            if self.lines[idx].0.is_some() {
+                self.list_state.select(Some(idx));
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use ratatui::{
+    crossterm::event::{Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear},
+    Frame,
+};
+use zeroize::Zeroizing;
+
+pub(crate) struct ChangePasswordView {
+    focus: Focus,
+    current: Zeroizing<String>,
+    new: Zeroizing<String>,
+    repeated: Zeroizing<String>,
+    error_message: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Current,
+    New,
+    Repeated,
+}
+
+pub(crate) enum EditResult {
+    Cancel,
+    Confirm {
+        current: Zeroizing<String>,
+        new: Zeroizing<String>,
+    },
+}
+
+impl ChangePasswordView {
+    pub(crate) fn new() -> Self {
+        Self {
+            focus: Focus::Current,
+            current: Zeroizing::new(String::new()),
+            new: Zeroizing::new(String::new()),
+            repeated: Zeroizing::new(String::new()),
+            error_message: String::new(),
+        }
+    }
+
+    pub(crate) fn set_error_message(&mut self, message: String) {
+        self.error_message = message;
+    }
+
+    pub(crate) fn draw(&self, frame: &mut Frame<'_>) {
+        let [_, v_area, _] = Layout::vertical([
+            Constraint::Fill(1),
+            // 2 - box (incl. title)
+            // 3 - password fields
+            // 1 - message
+            // 1 - help
+            Constraint::Length(7),
+            Constraint::Fill(2),
+        ])
+        .areas(frame.area());
+        let [_, area, _] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Length(50),
+            Constraint::Fill(1),
+        ])
+        .areas(v_area);
+        let block = Block::new()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Green))
+            .title("change master password");
+        frame.render_widget(Clear, area);
+        frame.render_widget(&block, area);
+        let [current_area, new_area, repeated_area, message_area, help_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Fill(1),
+        ])
+        .areas(block.inner(area));
+
+        for (label, value, focus, field_area) in [
+            ("current: ", &self.current, Focus::Current, current_area),
+            ("new: ", &self.new, Focus::New, new_area),
+            ("repeat: ", &self.repeated, Focus::Repeated, repeated_area),
+        ] {
+            let style = if self.focus == focus {
+                Style::default().fg(Color::Green)
+            } else {
+                Style::default()
+            };
+            let line = Line::default().spans([
+                Span::styled(label, style),
+                Span::styled("*".repeat(value.len()), style),
+            ]);
+            frame.render_widget(line, field_area);
+        }
+
+        let error_message = Line::default().spans([Span::styled(
+            self.error_message.clone(),
+            Style::default().fg(Color::Red),
+        )]);
+        frame.render_widget(error_message, message_area);
+
+        let help_message = Line::default().spans([Span::styled(
+            "<Enter> - confirm <Esc> - cancel",
+            Style::default(),
+        )]);
+        frame.render_widget(help_message, help_area);
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &Event) -> Option<EditResult> {
+        let Event::Key(key_event) = event else {
+            return None;
+        };
+        if key_event.kind != KeyEventKind::Press {
+            return None;
+        }
+
+        match key_event.code {
+            KeyCode::Enter => {
+                if self.new.is_empty() {
+                    self.error_message = "New password must not be empty".to_string();
+                } else if self.new != self.repeated {
+                    self.error_message = "Passwords do not match".to_string();
+                } else {
+                    return Some(EditResult::Confirm {
+                        current: self.current.clone(),
+                        new: self.new.clone(),
+                    });
+                }
+            }
+            KeyCode::Esc => {
+                return Some(EditResult::Cancel);
+            }
+            KeyCode::Up | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Current => Focus::Repeated,
+                    Focus::New => Focus::Current,
+                    Focus::Repeated => Focus::New,
+                };
+            }
+            KeyCode::Down | KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Current => Focus::New,
+                    Focus::New => Focus::Repeated,
+                    Focus::Repeated => Focus::Current,
+                };
+            }
+            KeyCode::Char(c) => {
+                self.focused_field().push(c);
+                self.error_message.clear();
+            }
+            KeyCode::Backspace => {
+                self.focused_field().pop();
+                self.error_message.clear();
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn focused_field(&mut self) -> &mut Zeroizing<String> {
+        match self.focus {
+            Focus::Current => &mut self.current,
+            Focus::New => &mut self.new,
+            Focus::Repeated => &mut self.repeated,
+        }
+    }
+}
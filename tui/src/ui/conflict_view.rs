@@ -0,0 +1,104 @@
+use ratatui::{
+    crossterm::event::{Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame,
+};
+
+/// Asks what to do when the database file was changed by another program
+/// since it was loaded.
+pub(crate) struct ConflictView {
+    error_message: String,
+}
+
+pub(crate) enum Resolution {
+    Reload,
+    Overwrite,
+    Merge,
+}
+
+pub(crate) enum EditResult {
+    Cancel,
+    Resolve(Resolution),
+}
+
+impl ConflictView {
+    pub(crate) fn new() -> Self {
+        Self {
+            error_message: String::new(),
+        }
+    }
+
+    pub(crate) fn set_error_message(&mut self, message: String) {
+        self.error_message = message;
+    }
+
+    pub(crate) fn draw(&self, frame: &mut Frame<'_>) {
+        let [_, v_area, _] = Layout::vertical([
+            Constraint::Fill(1),
+            // 2 - box (incl. title)
+            // 2 - explanation
+            // 1 - message
+            // 1 - help
+            Constraint::Length(6),
+            Constraint::Fill(2),
+        ])
+        .areas(frame.area());
+        let [_, area, _] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Length(70),
+            Constraint::Fill(1),
+        ])
+        .areas(v_area);
+        let block = Block::new()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Red))
+            .title("database changed on disk");
+        frame.render_widget(Clear, area);
+        frame.render_widget(&block, area);
+        let [text_area, message_area, help_area] = Layout::vertical([
+            Constraint::Length(2),
+            Constraint::Length(1),
+            Constraint::Fill(1),
+        ])
+        .areas(block.inner(area));
+
+        let text = vec![
+            Line::from("Another program saved the database since it was opened."),
+            Line::from("Saving now would erase its changes."),
+        ];
+        frame.render_widget(Paragraph::new(text), text_area);
+
+        let error_message = Line::default().spans([Span::styled(
+            self.error_message.clone(),
+            Style::default().fg(Color::Red),
+        )]);
+        frame.render_widget(error_message, message_area);
+
+        let help_message = Line::default().spans([Span::styled(
+            "<r> reload <o> overwrite <m> merge <Esc> cancel",
+            Style::default(),
+        )]);
+        frame.render_widget(help_message, help_area);
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &Event) -> Option<EditResult> {
+        let Event::Key(key_event) = event else {
+            return None;
+        };
+        if key_event.kind != KeyEventKind::Press {
+            return None;
+        }
+        self.error_message.clear();
+
+        match key_event.code {
+            KeyCode::Char('r') => Some(EditResult::Resolve(Resolution::Reload)),
+            KeyCode::Char('o') => Some(EditResult::Resolve(Resolution::Overwrite)),
+            KeyCode::Char('m') => Some(EditResult::Resolve(Resolution::Merge)),
+            KeyCode::Esc => Some(EditResult::Cancel),
+            _ => None,
+        }
+    }
+}
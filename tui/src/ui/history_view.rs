@@ -0,0 +1,138 @@
+use chrono::Local;
+use cred_man_lib::DbRecord;
+use ratatui::{
+    crossterm::event::{Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, List, ListState},
+    Frame,
+};
+use zeroize::Zeroizing;
+
+use super::first_line;
+
+/// Previous revisions of a record, most recent first.
+pub(crate) struct HistoryView {
+    key_name: String,
+    /// Indices into `DbRecord::history` together with their display lines
+    revisions: Vec<(usize, Zeroizing<String>)>,
+    list_state: ListState,
+}
+
+pub(crate) enum EditResult {
+    Cancel,
+    Restore { key_name: String, idx: usize },
+}
+
+impl HistoryView {
+    pub(crate) fn new(record: &DbRecord, reveal_data: bool) -> Self {
+        let revisions = record
+            .history
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(idx, revision)| {
+                let values = revision
+                    .value
+                    .iter()
+                    .map(|(name, value)| {
+                        if reveal_data || !record.is_sensitive(name) {
+                            format!("{name}: {}", first_line(value))
+                        } else {
+                            format!("{name}: ***")
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let line = format!(
+                    "{} {values}",
+                    revision
+                        .timestamp
+                        .with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M:%S")
+                );
+                (idx, Zeroizing::new(line))
+            })
+            .collect();
+        Self {
+            key_name: record.key.clone(),
+            revisions,
+            list_state: ListState::default().with_selected(Some(0)),
+        }
+    }
+
+    pub(crate) fn draw(&mut self, frame: &mut Frame<'_>) {
+        let [_, v_area, _] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(14),
+            Constraint::Fill(1),
+        ])
+        .areas(frame.area());
+        let [_, area, _] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Percentage(80),
+            Constraint::Fill(1),
+        ])
+        .areas(v_area);
+        let block = Block::new()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Green))
+            .title(format!("history of {}", self.key_name));
+        let [list_area, help_area] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(block.inner(area));
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
+
+        if self.revisions.is_empty() {
+            frame.render_widget(Line::from("no previous revisions"), list_area);
+        } else {
+            let list = List::new(self.revisions.iter().map(|(_, line)| line.as_str()))
+                .highlight_style(Style::new().bg(Color::Green).fg(Color::Black))
+                .highlight_symbol(">");
+            frame.render_stateful_widget(list, list_area, &mut self.list_state);
+        }
+
+        let help_message = Line::default().spans([Span::styled(
+            "<Enter> - restore <Esc> - close",
+            Style::default(),
+        )]);
+        frame.render_widget(help_message, help_area);
+    }
+
+    pub(crate) fn handle_event(&mut self, event: &Event) -> Option<EditResult> {
+        let Event::Key(key_event) = event else {
+            return None;
+        };
+        if key_event.kind != KeyEventKind::Press {
+            return None;
+        }
+
+        match key_event.code {
+            KeyCode::Enter => {
+                if let Some(&(idx, _)) = self
+                    .list_state
+                    .selected()
+                    .and_then(|selected| self.revisions.get(selected))
+                {
+                    return Some(EditResult::Restore {
+                        key_name: self.key_name.clone(),
+                        idx,
+                    });
+                }
+            }
+            KeyCode::Esc => {
+                return Some(EditResult::Cancel);
+            }
+            KeyCode::Up => {
+                self.list_state.select_previous();
+            }
+            KeyCode::Down => {
+                self.list_state.select_next();
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
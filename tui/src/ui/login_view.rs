@@ -1,4 +1,3 @@
-use anyhow::Context;
 use ratatui::{
     crossterm::event::{Event, KeyCode, KeyEventKind},
     layout::{Constraint, Layout},
@@ -8,33 +7,43 @@ use ratatui::{
     Frame,
 };
 
+use zeroize::Zeroizing;
+
+use anyhow::Context;
+use cred_man_lib::{strength, Error};
+
 use crate::app_state::AppState;
 
-use super::{AppView, EventHandleResult, MainView};
+use super::{strength_span, AppView, EventHandleResult, MainView};
 
 pub(crate) struct LoginView {
-    password: String,
-    show_error: bool,
+    password: Zeroizing<String>,
+    error_message: Option<String>,
 }
 
 impl LoginView {
     pub(crate) fn new() -> Self {
         Self {
-            password: String::new(),
-            show_error: false,
+            password: Zeroizing::new(String::new()),
+            error_message: None,
         }
     }
 
-    pub(crate) fn draw(&mut self, _app_state: &mut AppState, frame: &mut Frame<'_>) {
+    pub(crate) fn draw(&mut self, app_state: &mut AppState, frame: &mut Frame<'_>) {
+        let is_new = app_state
+            .view()
+            .into_not_opened()
+            .is_some_and(|app_view| app_view.is_new());
         let [_, v_area, _] = Layout::vertical([
             Constraint::Fill(1),
-            Constraint::Length(4),
+            // 2 - box, 1 - password, 1 - message, 1 - strength of a new one
+            Constraint::Length(if is_new { 5 } else { 4 }),
             Constraint::Fill(2),
         ])
         .areas(frame.area());
         let [_, area, _] = Layout::horizontal([
             Constraint::Fill(1),
-            Constraint::Length(30),
+            Constraint::Length(if is_new { 70 } else { 50 }),
             Constraint::Fill(1),
         ])
         .areas(v_area);
@@ -43,14 +52,29 @@ impl LoginView {
         let block = Block::new()
             .borders(Borders::ALL)
             .border_style(Style::default().fg(Color::Green))
-            .title("Enter password");
-        let [password_area, message_area] =
-            Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(block.inner(area));
+            .title(if is_new {
+                "Choose a password for the new database"
+            } else {
+                "Enter password"
+            });
+        let [password_area, strength_area, message_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(u16::from(is_new)),
+            Constraint::Fill(1),
+        ])
+        .areas(block.inner(area));
         frame.render_widget(block, area);
         frame.render_widget(password_line, password_area);
-        if self.show_error {
+        if is_new && !self.password.is_empty() {
+            let strength = strength::estimate(&self.password, &[]);
+            frame.render_widget(
+                Line::default().spans([strength_span(&strength)]),
+                strength_area,
+            );
+        }
+        if let Some(error_message) = &self.error_message {
             let error_message = Line::default().spans([Span::styled(
-                "Invalid password",
+                error_message.as_str(),
                 Style::default().fg(Color::Red),
             )]);
             frame.render_widget(error_message, message_area);
@@ -75,15 +99,25 @@ impl LoginView {
         }
 
         match key_event.code {
-            KeyCode::Enter => {
-                if app_view.open(&self.password).context("open db")? {
+            KeyCode::Enter => match app_view.open(&self.password) {
+                Ok(()) => {
                     return Ok(EventHandleResult::ChangeView(AppView::Main(MainView::new(
                         app_state,
                     ))));
                 }
-
-                self.show_error = true;
-            }
+                Err(Error::WrongPassword) => {
+                    self.error_message = Some("Invalid password".to_string());
+                }
+                Err(Error::KeyFileRequired) => {
+                    self.error_message = Some("Key file required".to_string());
+                }
+                Err(Error::Io(e)) => {
+                    return Err(e).context("open db");
+                }
+                Err(e) => {
+                    self.error_message = Some(e.to_string());
+                }
+            },
             KeyCode::Esc => {
                 return Ok(EventHandleResult::Quit);
             }
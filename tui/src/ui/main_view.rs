@@ -1,21 +1,27 @@
-use std::collections::BTreeMap;
+use indexmap::IndexMap;
 
 use anyhow::Context;
-use chrono::Local;
+use chrono::Utc;
 use cli_clipboard::ClipboardProvider;
-use cred_man_lib::DbRecord;
+use cred_man_lib::{otp, DbRecord, Error};
 use ratatui::{
-    crossterm::event::{Event, KeyCode, KeyEventKind},
+    crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers},
     layout::{Constraint, Layout},
     style::{Color, Style},
     text::{Line, Span},
     widgets::{Block, Borders, List, ListState, Paragraph},
     Frame,
 };
+use zeroize::Zeroizing;
 
 use crate::app_state::{AppState, AppStateOpened};
 
 use super::{
+    audit_view::{self, AuditView},
+    change_password_view::{self, ChangePasswordView},
+    conflict_view::{self, ConflictView},
+    first_line,
+    history_view::{self, HistoryView},
     key_name_edit_view::{self, KeyNameEditMode, KeyNameEditView},
     subkey_edit_view::{self, SubkeyEditView},
     EventHandleResult,
@@ -24,9 +30,12 @@ use super::{
 pub(crate) struct MainView {
     search: String,
     search_results: Vec<String>,
+    /// Only records with this tag are listed
+    tag_filter: Option<String>,
     focus: MainViewFocus,
     list_state: ListState,
     sublist_state: ListState,
+    tags_state: ListState,
     scroll_page_size: u16,
     reveal_data: bool,
     subview: Option<MainViewSubview>,
@@ -36,6 +45,10 @@ pub(crate) struct MainView {
 enum MainViewSubview {
     EditingKey(Box<KeyNameEditView>),
     EditSubkey(Box<SubkeyEditView>),
+    ChangePassword(Box<ChangePasswordView>),
+    Conflict(Box<ConflictView>),
+    History(Box<HistoryView>),
+    Audit(Box<AuditView>),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -43,6 +56,7 @@ enum MainViewFocus {
     Search,
     List,
     Sublist,
+    Tags,
 }
 
 impl MainView {
@@ -54,8 +68,10 @@ impl MainView {
         Self {
             search: String::new(),
             search_results: app_view.db().data.keys().cloned().collect(),
+            tag_filter: None,
             list_state: ListState::default().with_selected(Some(0)),
             sublist_state: ListState::default().with_selected(Some(0)),
+            tags_state: ListState::default().with_selected(Some(0)),
             scroll_page_size: 1,
             focus: MainViewFocus::Search,
             reveal_data: false,
@@ -78,8 +94,12 @@ impl MainView {
             Constraint::Length(1),
         ])
         .areas(frame.area());
-        let [list_area, sublist_area] =
-            Layout::horizontal([Constraint::Fill(1), Constraint::Fill(2)]).areas(main_area);
+        let [tags_area, list_area, sublist_area] = Layout::horizontal([
+            Constraint::Length(20),
+            Constraint::Fill(1),
+            Constraint::Fill(2),
+        ])
+        .areas(main_area);
 
         let focused_block_style = Style::new().fg(Color::Green);
         let default_block_style = Style::new();
@@ -100,6 +120,12 @@ impl MainView {
             default_block_style
         };
 
+        let tags_border_style = if self.subview.is_none() && self.focus == MainViewFocus::Tags {
+            focused_block_style
+        } else {
+            default_block_style
+        };
+
         let search = Paragraph::new(self.search.clone()).block(
             Block::new()
                 .borders(Borders::ALL)
@@ -108,9 +134,35 @@ impl MainView {
         );
         frame.render_widget(search, search_area);
 
+        let tags = app_state.db().tags();
+        self.tags_state.select(Some(
+            self.tag_filter
+                .as_deref()
+                .and_then(|tag| tags.iter().position(|t| *t == tag))
+                .map_or(0, |idx| idx + 1),
+        ));
+        let tags_list = List::new(
+            std::iter::once("(all)")
+                .chain(tags.iter().copied())
+                .map(ToString::to_string),
+        )
+        .highlight_style(Style::new().bg(Color::Green).fg(Color::Black))
+        .highlight_symbol(">")
+        .block(
+            Block::new()
+                .borders(Borders::ALL)
+                .title("Tags")
+                .border_style(tags_border_style),
+        );
+        frame.render_stateful_widget(tags_list, tags_area, &mut self.tags_state);
+
+        let list_title = match app_state.read_only_reason {
+            Some(reason) => format!("Credentials (read-only: {reason})"),
+            None => "Credentials".to_string(),
+        };
         let list_block = Block::new()
             .borders(Borders::ALL)
-            .title("Credentials")
+            .title(list_title)
             .border_style(list_border_style);
         let list = List::new(self.search_results.clone())
             .highlight_style(Style::new().bg(Color::Green).fg(Color::Black))
@@ -118,21 +170,38 @@ impl MainView {
         self.scroll_page_size = list_block.inner(list_area).height.saturating_sub(1);
         frame.render_stateful_widget(list.block(list_block), list_area, &mut self.list_state);
 
-        let empty_btreemap = BTreeMap::new();
-        let sublist_data = self
+        let empty_map = IndexMap::new();
+        let selected_record = self
             .list_state
             .selected()
             .and_then(|idx| self.search_results.get(idx))
-            .and_then(|key| app_state.db().data.get(key))
-            .map_or(&empty_btreemap, |rec| &rec.value);
+            .and_then(|key| app_state.db().data.get(key));
+        let sublist_data = selected_record.map_or(&empty_map, |rec| &rec.value);
+        let sublist_title = match selected_record.and_then(|rec| rec.folder.as_deref()) {
+            Some(folder) => format!("Credentials data (in {folder})"),
+            None => "Credentials data".to_string(),
+        };
 
         let sublist_block = Block::new()
             .borders(Borders::ALL)
-            .title("Credentials data")
+            .title(sublist_title)
             .border_style(sublist_border_style);
+        let read_only = app_state.db().is_read_only();
+        let otp = selected_record.and_then(otp::find_in);
         let sublist = List::new(sublist_data.iter().map(|(key, value)| {
-            if self.reveal_data {
-                format!("{key}: {value}")
+            if let Some((_, otp)) = otp.as_ref().filter(|(name, _)| name == key) {
+                match otp {
+                    Ok(otp) => match otp.now() {
+                        (code, Some(remaining)) => format!("{key}: {code} ({remaining}s)"),
+                        // The used-up HOTP counter could not be saved.
+                        (_, None) if read_only => format!("{key}: *** ({})", Error::ReadOnly),
+                        (_, None) => format!("{key}: *** (<c> copies the next code)"),
+                    },
+                    Err(e) => format!("{key}: *** ({e})"),
+                }
+            } else if self.reveal_data || selected_record.is_some_and(|rec| !rec.is_sensitive(key))
+            {
+                format!("{key}: {}", first_line(value))
             } else {
                 format!("{key}: ***")
             }
@@ -145,32 +214,42 @@ impl MainView {
             &mut self.sublist_state,
         );
 
+        let save_help = if read_only {
+            Span::raw("")
+        } else {
+            Span::styled(
+                " <s> save",
+                Style::default().fg(if self.is_dirty {
+                    Color::Red
+                } else {
+                    Color::Gray
+                }),
+            )
+        };
         let help: Vec<Span> = match self.focus {
             MainViewFocus::Search => vec!["<Tab>/<Shift-Tab> switch".into()],
+            MainViewFocus::Tags => {
+                vec!["<Tab>/<Shift-Tab> switch <Up>/<Down> filter by tag".into()]
+            }
+            MainViewFocus::List if read_only => {
+                vec!["<Tab>/<Shift-Tab> switch <h> history <a> audit".into()]
+            }
             MainViewFocus::List => {
                 vec![
-                    "<Tab>/<Shift-Tab> switch <n> new <r> rename <d> delete".into(),
-                    Span::styled(
-                        " <s> save",
-                        Style::default().fg(if self.is_dirty {
-                            Color::Red
-                        } else {
-                            Color::Gray
-                        }),
-                    ),
+                    "<Tab>/<Shift-Tab> switch <n> new <r> rename <d> delete <h> history <a> audit <p> change password"
+                        .into(),
+                    save_help,
+                ]
+            }
+            MainViewFocus::Sublist if read_only => {
+                vec![
+                    "<Tab>/<Shift-Tab> switch <c> copy to clipboard <v> reveal/hide secrets".into(),
                 ]
             }
             MainViewFocus::Sublist => {
                 vec![
-                    "<Tab>/<Shift-Tab> switch <c> copy to clipboard <v> reveal/hide values <n> new <e> edit <d> del".into(),
-                    Span::styled(
-                        " <s> save",
-                        Style::default().fg(if self.is_dirty {
-                            Color::Red
-                        } else {
-                            Color::Gray
-                        }),
-                    ),
+                    "<Tab>/<Shift-Tab> switch <c> copy to clipboard <v> reveal/hide secrets <m> mark secret/plain <S-Up>/<S-Down> move <n> new <e> edit <d> del".into(),
+                    save_help,
                 ]
             }
         };
@@ -181,6 +260,10 @@ impl MainView {
             match subview {
                 MainViewSubview::EditingKey(key_name_edit_view) => key_name_edit_view.draw(frame),
                 MainViewSubview::EditSubkey(view) => view.draw(frame),
+                MainViewSubview::ChangePassword(view) => view.draw(frame),
+                MainViewSubview::Conflict(view) => view.draw(frame),
+                MainViewSubview::History(view) => view.draw(frame),
+                MainViewSubview::Audit(view) => view.draw(frame),
             }
         }
     }
@@ -215,14 +298,18 @@ impl MainView {
             {
                 self.search = String::new();
             }
+            if let Some(tag) = &self.tag_filter {
+                if app_state
+                    .db()
+                    .data
+                    .get(selected_key)
+                    .is_some_and(|record| !record.tags.contains(tag))
+                {
+                    self.tag_filter = None;
+                }
+            }
         }
-        self.search_results = app_state
-            .db()
-            .data
-            .keys()
-            .filter(|s| s.to_lowercase().contains(&self.search.to_lowercase()))
-            .cloned()
-            .collect();
+        self.search_results = self.matching_keys(app_state);
         let selected_idx = selected_key
             .and_then(|selected_key| self.search_results.iter().position(|s| *s == selected_key));
         let selected_main_record =
@@ -239,6 +326,39 @@ impl MainView {
             self.sublist_state.select_first();
         }
     }
+
+    /// Keys that match the search and the tag filter.
+    fn matching_keys(&self, app_state: &AppStateOpened) -> Vec<String> {
+        let search = self.search.to_lowercase();
+        app_state
+            .db()
+            .data
+            .values()
+            .filter(|record| record.key.to_lowercase().contains(&search))
+            .filter(|record| {
+                self.tag_filter
+                    .as_ref()
+                    .is_none_or(|tag| record.tags.contains(tag))
+            })
+            .map(|record| record.key.clone())
+            .collect()
+    }
+
+    /// Moves the tag filter by `offset` entries in the sidebar; the first
+    /// entry shows all records.
+    fn move_tag_filter(&mut self, app_state: &AppStateOpened, offset: isize) {
+        let tags = app_state.db().tags();
+        let current = self.tags_state.selected().unwrap_or(0);
+        let selected = current.saturating_add_signed(offset).min(tags.len());
+        self.tag_filter = selected
+            .checked_sub(1)
+            .and_then(|idx| tags.iter().nth(idx))
+            .map(ToString::to_string);
+        self.tags_state.select(Some(selected));
+        self.search_results = self.matching_keys(app_state);
+        self.list_state.select_first();
+        self.sublist_state.select_first();
+    }
 }
 
 impl MainView {
@@ -263,37 +383,27 @@ impl MainView {
                 self.focus = match self.focus {
                     MainViewFocus::Search => MainViewFocus::List,
                     MainViewFocus::List => MainViewFocus::Sublist,
-                    MainViewFocus::Sublist => MainViewFocus::Search,
+                    MainViewFocus::Sublist => MainViewFocus::Tags,
+                    MainViewFocus::Tags => MainViewFocus::Search,
                 };
             }
             KeyCode::BackTab => {
                 self.focus = match self.focus {
-                    MainViewFocus::Search => MainViewFocus::Sublist,
+                    MainViewFocus::Search => MainViewFocus::Tags,
                     MainViewFocus::List => MainViewFocus::Search,
                     MainViewFocus::Sublist => MainViewFocus::List,
+                    MainViewFocus::Tags => MainViewFocus::Sublist,
                 };
             }
             KeyCode::Char(c) if self.focus == MainViewFocus::Search => {
                 self.search.push(c);
-                self.search_results = app_state
-                    .db()
-                    .data
-                    .keys()
-                    .filter(|s| s.to_lowercase().contains(&self.search.to_lowercase()))
-                    .cloned()
-                    .collect();
+                self.search_results = self.matching_keys(app_state);
                 self.list_state.select_first();
                 self.sublist_state.select_first();
             }
             KeyCode::Backspace if self.focus == MainViewFocus::Search => {
                 self.search.pop();
-                self.search_results = app_state
-                    .db()
-                    .data
-                    .keys()
-                    .filter(|s| s.to_lowercase().contains(&self.search.to_lowercase()))
-                    .cloned()
-                    .collect();
+                self.search_results = self.matching_keys(app_state);
                 self.list_state.select_first();
                 self.sublist_state.select_first();
             }
@@ -339,16 +449,21 @@ impl MainView {
                 self.list_state.select_last();
                 self.sublist_state.select_first();
             }
-            KeyCode::Char('s') if self.focus == MainViewFocus::List => {
-                app_state.db.save().context("save db")?;
-                self.is_dirty = false;
+            KeyCode::Char('s')
+                if self.focus == MainViewFocus::List && !app_state.db.is_read_only() =>
+            {
+                self.save(app_state)?;
             }
-            KeyCode::Char('n') if self.focus == MainViewFocus::List => {
+            KeyCode::Char('n')
+                if self.focus == MainViewFocus::List && !app_state.db.is_read_only() =>
+            {
                 self.subview = Some(MainViewSubview::EditingKey(Box::new(KeyNameEditView::new(
                     KeyNameEditMode::NewKey,
                 ))));
             }
-            KeyCode::Char('d') if self.focus == MainViewFocus::List => {
+            KeyCode::Char('d')
+                if self.focus == MainViewFocus::List && !app_state.db.is_read_only() =>
+            {
                 if let Some(idx) = self.list_state.selected() {
                     if let Some(key) = self.search_results.get(idx) {
                         app_state.db.data.remove(key);
@@ -357,7 +472,34 @@ impl MainView {
                     }
                 }
             }
-            KeyCode::Char('r') if self.focus == MainViewFocus::List => {
+            KeyCode::Char('h') if self.focus == MainViewFocus::List => {
+                if let Some(record) = self
+                    .list_state
+                    .selected()
+                    .and_then(|idx| self.search_results.get(idx))
+                    .and_then(|key| app_state.db.data.get(key))
+                {
+                    self.subview = Some(MainViewSubview::History(Box::new(HistoryView::new(
+                        record,
+                        self.reveal_data,
+                    ))));
+                }
+            }
+            KeyCode::Char('a') if self.focus == MainViewFocus::List => {
+                self.subview = Some(MainViewSubview::Audit(Box::new(AuditView::new(
+                    &app_state.db.data,
+                ))));
+            }
+            KeyCode::Char('p')
+                if self.focus == MainViewFocus::List && !app_state.db.is_read_only() =>
+            {
+                self.subview = Some(MainViewSubview::ChangePassword(Box::new(
+                    ChangePasswordView::new(),
+                )));
+            }
+            KeyCode::Char('r')
+                if self.focus == MainViewFocus::List && !app_state.db.is_read_only() =>
+            {
                 if let Some(idx) = self.list_state.selected() {
                     let key = self.search_results[idx].clone();
                     self.subview = Some(MainViewSubview::EditingKey(Box::new(
@@ -365,6 +507,45 @@ impl MainView {
                     )));
                 }
             }
+            KeyCode::Up if self.focus == MainViewFocus::Tags => {
+                self.move_tag_filter(app_state, -1);
+            }
+            KeyCode::Down if self.focus == MainViewFocus::Tags => {
+                self.move_tag_filter(app_state, 1);
+            }
+            KeyCode::Home if self.focus == MainViewFocus::Tags => {
+                self.move_tag_filter(app_state, isize::MIN);
+            }
+            KeyCode::End if self.focus == MainViewFocus::Tags => {
+                self.move_tag_filter(app_state, isize::MAX);
+            }
+            KeyCode::Up | KeyCode::Down
+                if self.focus == MainViewFocus::Sublist
+                    && key_event.modifiers.contains(KeyModifiers::SHIFT)
+                    && !app_state.db.is_read_only() =>
+            {
+                if let Some(selected_key) = self
+                    .list_state
+                    .selected()
+                    .and_then(|idx| self.search_results.get(idx))
+                    .and_then(|key| app_state.db.data.get_mut(key))
+                {
+                    if let Some(sublist_idx) = self.sublist_state.selected() {
+                        let to = if key_event.code == KeyCode::Up {
+                            sublist_idx.saturating_sub(1)
+                        } else {
+                            sublist_idx + 1
+                        };
+                        if let Some(subkey) = selected_key.value.keys().nth(sublist_idx).cloned() {
+                            if to < selected_key.value.len() && to != sublist_idx {
+                                selected_key.move_field(&subkey, to);
+                                self.sublist_state.select(Some(to));
+                                self.is_dirty = true;
+                            }
+                        }
+                    }
+                }
+            }
             KeyCode::Up if self.focus == MainViewFocus::Sublist => {
                 self.sublist_state.select_previous();
             }
@@ -387,15 +568,34 @@ impl MainView {
                 if let (Some(list_idx), Some(sublist_idx)) =
                     (self.list_state.selected(), self.sublist_state.selected())
                 {
-                    if let Some((_key, value)) = self
+                    if let Some((record, (subkey, value))) = self
                         .search_results
                         .get(list_idx)
-                        .and_then(|key| app_state.db.data.get(key))
-                        .and_then(|rec| rec.value.iter().nth(sublist_idx))
+                        .and_then(|key| app_state.db.access(key))
+                        .and_then(|rec| Some((rec, rec.value.iter().nth(sublist_idx)?)))
                     {
+                        let mut value = value.clone();
+                        if let Some((_, otp)) =
+                            otp::find_in(record).filter(|(name, _)| name == subkey)
+                        {
+                            let is_hotp =
+                                otp.is_ok_and(|otp| matches!(otp.kind, otp::OtpKind::Hotp { .. }));
+                            if is_hotp && app_state.db.is_read_only() {
+                                // The used-up counter could not be saved; the
+                                // field says so.
+                                return Ok(EventHandleResult::Continue);
+                            }
+                            let key = &self.search_results[list_idx];
+                            let record = app_state.db.data.get_mut(key).context("record")?;
+                            if let Some(Ok((code, remaining))) = otp::next_code(record) {
+                                // An HOTP code is used up.
+                                self.is_dirty |= remaining.is_none();
+                                value = code;
+                            }
+                        }
                         if let Some(clipboard) = app_state.clipboard {
                             clipboard
-                                .set_contents(value.clone())
+                                .set_contents(value)
                                 .map_err(|error| anyhow::anyhow!("{error:?}"))
                                 .context("clipboard copy")?;
                         }
@@ -405,18 +605,45 @@ impl MainView {
             KeyCode::Char('v') if self.focus == MainViewFocus::Sublist => {
                 self.reveal_data = !self.reveal_data;
             }
-            KeyCode::Char('n') if self.focus == MainViewFocus::Sublist => {
+            KeyCode::Char('n')
+                if self.focus == MainViewFocus::Sublist && !app_state.db.is_read_only() =>
+            {
                 if let Some(idx) = self.list_state.selected() {
-                    if let Some(key) = self.search_results.get(idx) {
-                        self.subview = Some(MainViewSubview::EditSubkey(Box::new(
-                            SubkeyEditView::new(subkey_edit_view::EditingMode::NewSubkey {
-                                key_name: key.clone(),
-                            }),
-                        )));
+                    if let Some(record) = self
+                        .search_results
+                        .get(idx)
+                        .and_then(|key| app_state.db.data.get(key))
+                    {
+                        self.subview =
+                            Some(MainViewSubview::EditSubkey(Box::new(SubkeyEditView::new(
+                                subkey_edit_view::EditingMode::NewSubkey {
+                                    key_name: record.key.clone(),
+                                },
+                                record,
+                            ))));
+                    }
+                }
+            }
+            KeyCode::Char('d')
+                if self.focus == MainViewFocus::Sublist && !app_state.db.is_read_only() =>
+            {
+                if let Some(selected_key) = self
+                    .list_state
+                    .selected()
+                    .and_then(|idx| self.search_results.get(idx))
+                    .and_then(|key| app_state.db.data.get_mut(key))
+                {
+                    if let Some(sublist_idx) = self.sublist_state.selected() {
+                        if let Some(subkey) = selected_key.value.keys().nth(sublist_idx).cloned() {
+                            selected_key.remove_field(&subkey);
+                            self.is_dirty = true;
+                        }
                     }
                 }
             }
-            KeyCode::Char('d') if self.focus == MainViewFocus::Sublist => {
+            KeyCode::Char('m')
+                if self.focus == MainViewFocus::Sublist && !app_state.db.is_read_only() =>
+            {
                 if let Some(selected_key) = self
                     .list_state
                     .selected()
@@ -425,12 +652,17 @@ impl MainView {
                 {
                     if let Some(sublist_idx) = self.sublist_state.selected() {
                         if let Some(subkey) = selected_key.value.keys().nth(sublist_idx).cloned() {
-                            selected_key.value.remove(&subkey);
+                            let mut meta = selected_key.field_meta(&subkey);
+                            meta.sensitive = !meta.sensitive;
+                            selected_key.set_field_meta(&subkey, meta);
+                            self.is_dirty = true;
                         }
                     }
                 }
             }
-            KeyCode::Char('e') if self.focus == MainViewFocus::Sublist => {
+            KeyCode::Char('e')
+                if self.focus == MainViewFocus::Sublist && !app_state.db.is_read_only() =>
+            {
                 if let Some(selected_key) = self
                     .list_state
                     .selected()
@@ -439,20 +671,23 @@ impl MainView {
                 {
                     if let Some(sublist_idx) = self.sublist_state.selected() {
                         if let Some((subkey, value)) = selected_key.value.iter().nth(sublist_idx) {
-                            self.subview = Some(MainViewSubview::EditSubkey(Box::new(
-                                SubkeyEditView::new(subkey_edit_view::EditingMode::EditSubkey {
-                                    key_name: selected_key.key.clone(),
-                                    name: subkey.clone(),
-                                    value: value.clone(),
-                                }),
-                            )));
+                            self.subview =
+                                Some(MainViewSubview::EditSubkey(Box::new(SubkeyEditView::new(
+                                    subkey_edit_view::EditingMode::EditSubkey {
+                                        key_name: selected_key.key.clone(),
+                                        name: subkey.clone(),
+                                        value: Zeroizing::new(value.clone()),
+                                    },
+                                    selected_key,
+                                ))));
                         }
                     }
                 }
             }
-            KeyCode::Char('s') if self.focus == MainViewFocus::Sublist => {
-                app_state.db.save().context("save db")?;
-                self.is_dirty = false;
+            KeyCode::Char('s')
+                if self.focus == MainViewFocus::Sublist && !app_state.db.is_read_only() =>
+            {
+                self.save(app_state)?;
             }
             _ => {}
         }
@@ -460,11 +695,27 @@ impl MainView {
         Ok(EventHandleResult::Continue)
     }
 
+    /// Saves the database, or asks what to do if someone else saved it in
+    /// the meantime.
+    fn save(&mut self, app_state: &mut AppStateOpened<'_>) -> anyhow::Result<()> {
+        match app_state.db.save() {
+            Ok(()) => {
+                self.is_dirty = false;
+            }
+            Err(Error::Conflict) => {
+                self.subview = Some(MainViewSubview::Conflict(Box::new(ConflictView::new())));
+            }
+            Err(e) => return Err(e).context("save db"),
+        }
+        Ok(())
+    }
+
     fn handle_subview_event(
         &mut self,
         app_state: &mut AppStateOpened<'_>,
         event: &Event,
     ) -> Option<EventHandleResult> {
+        #![allow(clippy::too_many_lines)]
         let Some(subview) = &mut self.subview else {
             return None;
         };
@@ -481,14 +732,10 @@ impl MainView {
                                 key_name_edit_view
                                     .set_error_message("this key already exists".into());
                             } else {
-                                app_state.db.data.insert(
-                                    name.clone(),
-                                    DbRecord {
-                                        key: name.clone(),
-                                        timestamp: Local::now().naive_local(),
-                                        value: BTreeMap::new(),
-                                    },
-                                );
+                                app_state
+                                    .db
+                                    .data
+                                    .insert(name.clone(), DbRecord::new(name.clone()));
                                 self.subview = None;
                                 self.refresh(app_state, Some(&name), None);
                                 self.is_dirty = true;
@@ -505,7 +752,7 @@ impl MainView {
                                     .remove(&from_name)
                                     .expect("the key existed before renaming");
                                 db_record.key.clone_from(&name);
-                                db_record.timestamp = Local::now().naive_local();
+                                db_record.modified = Utc::now();
                                 app_state.db.data.insert(name.clone(), db_record);
                                 self.subview = None;
                                 self.refresh(app_state, Some(&name), None);
@@ -520,7 +767,11 @@ impl MainView {
                 Some(subkey_edit_view::EditResult::Cancel) => {
                     self.subview = None;
                 }
-                Some(subkey_edit_view::EditResult::Confirm { mode, name, value }) => match mode {
+                Some(subkey_edit_view::EditResult::Confirm {
+                    mode,
+                    name,
+                    mut value,
+                }) => match mode {
                     subkey_edit_view::EditingMode::NewSubkey { key_name } => {
                         let db_record = app_state
                             .db
@@ -530,7 +781,10 @@ impl MainView {
                         if db_record.value.contains_key(&name) {
                             view.set_error_message("this attribute already exists".to_string());
                         } else {
-                            db_record.value.insert(name.clone(), value);
+                            db_record.new_revision();
+                            db_record
+                                .value
+                                .insert(name.clone(), std::mem::take(&mut *value));
                             self.subview = None;
                             self.refresh(app_state, Some(&key_name), Some(&name));
                             self.is_dirty = true;
@@ -549,8 +803,14 @@ impl MainView {
                         if name != old_name && db_record.value.contains_key(&name) {
                             view.set_error_message("this attribute already exists".to_string());
                         } else {
-                            db_record.value.remove(&old_name);
-                            db_record.value.insert(name.clone(), value);
+                            if name == old_name {
+                                db_record.new_revision();
+                            } else {
+                                db_record.rename_field(&old_name, &name);
+                            }
+                            db_record
+                                .value
+                                .insert(name.clone(), std::mem::take(&mut *value));
                             self.subview = None;
                             self.refresh(app_state, Some(&key_name), Some(&name));
                             self.is_dirty = true;
@@ -558,6 +818,73 @@ impl MainView {
                     }
                 },
             },
+            MainViewSubview::ChangePassword(view) => match view.handle_event(event) {
+                None => {}
+                Some(change_password_view::EditResult::Cancel) => {
+                    self.subview = None;
+                }
+                Some(change_password_view::EditResult::Confirm { current, new }) => {
+                    if !app_state.db.verify_password(&current) {
+                        view.set_error_message("wrong current password".to_string());
+                    } else if let Err(e) = app_state.db.change_password(&new) {
+                        view.set_error_message(format!("error: {e}"));
+                    } else {
+                        self.subview = None;
+                        self.is_dirty = false;
+                    }
+                }
+            },
+            MainViewSubview::History(view) => match view.handle_event(event) {
+                None => {}
+                Some(history_view::EditResult::Cancel) => {
+                    self.subview = None;
+                }
+                Some(history_view::EditResult::Restore { key_name, idx }) => {
+                    let read_only = app_state.db.is_read_only();
+                    if let Some(record) =
+                        app_state.db.data.get_mut(&key_name).filter(|_| !read_only)
+                    {
+                        if record.restore_revision(idx) {
+                            self.is_dirty = true;
+                        }
+                    }
+                    self.subview = None;
+                    self.refresh(app_state, Some(&key_name), None);
+                }
+            },
+            MainViewSubview::Audit(view) => match view.handle_event(event) {
+                None => {}
+                Some(audit_view::EditResult::Cancel) => {
+                    self.subview = None;
+                }
+                Some(audit_view::EditResult::Select { key_name }) => {
+                    self.subview = None;
+                    self.focus = MainViewFocus::List;
+                    self.refresh(app_state, Some(&key_name), None);
+                }
+            },
+            MainViewSubview::Conflict(view) => match view.handle_event(event) {
+                None => {}
+                Some(conflict_view::EditResult::Cancel) => {
+                    self.subview = None;
+                }
+                Some(conflict_view::EditResult::Resolve(resolution)) => {
+                    let result = match resolution {
+                        conflict_view::Resolution::Reload => app_state.db.reload(),
+                        conflict_view::Resolution::Overwrite => app_state.db.overwrite(),
+                        conflict_view::Resolution::Merge => {
+                            app_state.db.merge().and_then(|()| app_state.db.save())
+                        }
+                    };
+                    if let Err(e) = result {
+                        view.set_error_message(format!("error: {e}"));
+                    } else {
+                        self.subview = None;
+                        self.is_dirty = false;
+                        self.refresh(app_state, None, None);
+                    }
+                }
+            },
         }
 
         Some(EventHandleResult::Continue)
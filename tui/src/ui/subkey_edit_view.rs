@@ -1,18 +1,37 @@
+use cred_man_lib::generator::{self, PassphrasePolicy};
+use cred_man_lib::{strength, DbRecord, FieldKind, FieldMeta, PasswordPolicy};
 use ratatui::{
-    crossterm::event::{Event, KeyCode, KeyEventKind},
+    crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers},
     layout::{Constraint, Layout},
     style::{Color, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear},
+    widgets::{Block, Borders, Clear, Paragraph},
     Frame,
 };
+use std::collections::BTreeMap;
+use zeroize::Zeroizing;
+
+use super::strength_span;
+
+/// How many lines of a multi-line value are shown at once.
+const MAX_VALUE_LINES: u16 = 10;
 
 pub(crate) struct SubkeyEditView {
     mode: EditingMode,
     focus: Focus,
     name: String,
-    value: String,
+    /// May span several lines, see <Alt-Enter>
+    value: Zeroizing<String>,
+    /// For <Ctrl-G>
+    password_policy: PasswordPolicy,
+    /// Words of the record that make a password weak, see
+    /// [`strength::user_inputs`]
+    user_inputs: Vec<String>,
+    /// The record's field metadata, for the kind of the field being edited
+    fields: BTreeMap<String, FieldMeta>,
     error_message: String,
+    /// Shown when there is no error, e.g. the entropy of a generated value
+    notice: String,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -29,7 +48,7 @@ pub(crate) enum EditingMode {
     EditSubkey {
         key_name: String,
         name: String,
-        value: String,
+        value: Zeroizing<String>,
     },
 }
 
@@ -38,12 +57,18 @@ pub(crate) enum EditResult {
     Confirm {
         mode: EditingMode,
         name: String,
-        value: String,
+        value: Zeroizing<String>,
     },
 }
 
 impl SubkeyEditView {
-    pub(crate) fn new(mode: EditingMode) -> Self {
+    /// `record` is the one being edited, for its password policy and for
+    /// the strength of new passwords.
+    pub(crate) fn new(mode: EditingMode, record: &DbRecord) -> Self {
+        let user_inputs = match &mode {
+            EditingMode::NewSubkey { .. } => strength::user_inputs(record, ""),
+            EditingMode::EditSubkey { name, .. } => strength::user_inputs(record, name),
+        };
         Self {
             focus: Focus::Name,
             name: match &mode {
@@ -51,11 +76,15 @@ impl SubkeyEditView {
                 EditingMode::EditSubkey { name, .. } => name.clone(),
             },
             value: match &mode {
-                EditingMode::NewSubkey { .. } => String::new(),
+                EditingMode::NewSubkey { .. } => Zeroizing::new(String::new()),
                 EditingMode::EditSubkey { value, .. } => value.clone(),
             },
             mode,
+            password_policy: record.password_policy.unwrap_or_default(),
+            user_inputs: user_inputs.into_iter().map(ToString::to_string).collect(),
+            fields: record.fields.clone(),
             error_message: String::new(),
+            notice: String::new(),
         }
     }
 
@@ -64,14 +93,17 @@ impl SubkeyEditView {
     }
 
     pub(crate) fn draw(&self, frame: &mut Frame<'_>) {
+        // A trailing newline starts a line of its own.
+        let value_lines = u16::try_from(self.value.split('\n').count()).unwrap_or(u16::MAX);
+        let value_height = value_lines.min(MAX_VALUE_LINES);
         let [_, v_area, _] = Layout::vertical([
             Constraint::Fill(1),
             // 2 - box (incl. title)
             // 1 - name
-            // 1 - value
+            // value_height - value
             // 1 - message
             // 1 - help
-            Constraint::Length(6),
+            Constraint::Length(5 + value_height),
             Constraint::Fill(2),
         ])
         .areas(frame.area());
@@ -93,7 +125,7 @@ impl SubkeyEditView {
         frame.render_widget(&block, area);
         let [name_area, value_area, message_area, help_area] = Layout::vertical([
             Constraint::Length(1),
-            Constraint::Length(1),
+            Constraint::Length(value_height),
             Constraint::Length(1),
             Constraint::Fill(1),
         ])
@@ -119,39 +151,53 @@ impl SubkeyEditView {
         ]);
         frame.render_widget(name_line, name_area);
 
-        let value_line = Line::default().spans([
-            Span::styled(
-                "value: ",
-                if self.focus == Focus::Value {
-                    Style::default().fg(Color::Green)
-                } else {
-                    Style::default()
-                },
-            ),
-            Span::styled(
-                self.value.clone(),
-                if self.focus == Focus::Value {
-                    Style::default().fg(Color::Green)
-                } else {
-                    Style::default()
-                },
-            ),
-        ]);
-        frame.render_widget(value_line, value_area);
+        let value_style = if self.focus == Focus::Value {
+            Style::default().fg(Color::Green)
+        } else {
+            Style::default()
+        };
+        let [value_label_area, value_text_area] =
+            Layout::horizontal([Constraint::Length(7), Constraint::Fill(1)]).areas(value_area);
+        frame.render_widget(Span::styled("value: ", value_style), value_label_area);
+        // Keep the end of the value, where the typing happens, in view.
+        let value = Paragraph::new(self.value.as_str())
+            .style(value_style)
+            .scroll((value_lines - value_height, 0));
+        frame.render_widget(value, value_text_area);
 
-        let error_message = Line::default().spans([Span::styled(
-            self.error_message.clone(),
-            Style::default().fg(Color::Red),
-        )]);
-        frame.render_widget(error_message, message_area);
+        let message = if !self.error_message.is_empty() {
+            Span::styled(self.error_message.clone(), Style::default().fg(Color::Red))
+        } else if self.notice.is_empty() {
+            self.strength_message()
+        } else {
+            Span::styled(self.notice.clone(), Style::default().fg(Color::Gray))
+        };
+        frame.render_widget(Line::default().spans([message]), message_area);
 
         let help_message = Line::default().spans([Span::styled(
-            "<Enter> - confirm <Esc> - cancel",
+            "<Enter> - confirm <Alt-Enter> - new line <Ctrl-G>/<Ctrl-P> - generate password/passphrase <Esc> - cancel",
             Style::default(),
         )]);
         frame.render_widget(help_message, help_area);
     }
 
+    /// How hard the value is to guess, while typing a password.
+    fn strength_message(&self) -> Span<'static> {
+        let kind = self
+            .fields
+            .get(&self.name)
+            .map_or_else(|| FieldKind::guess(&self.name), |meta| meta.kind);
+        if self.value.is_empty() || kind != FieldKind::Password {
+            return Span::default();
+        }
+        let user_inputs = self
+            .user_inputs
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        strength_span(&strength::estimate(&self.value, &user_inputs))
+    }
+
     pub(crate) fn handle_event(&mut self, event: &Event) -> Option<EditResult> {
         let Event::Key(key_event) = event else {
             return None;
@@ -161,6 +207,42 @@ impl SubkeyEditView {
         }
 
         match key_event.code {
+            KeyCode::Enter
+                if self.focus == Focus::Value
+                    && key_event.modifiers.contains(KeyModifiers::ALT) =>
+            {
+                self.value.push('\n');
+                self.error_message.clear();
+            }
+            KeyCode::Char('g')
+                if self.focus == Focus::Value
+                    && key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                match generator::generate(&self.password_policy) {
+                    Ok(password) => {
+                        self.value.clone_from(&password);
+                        self.error_message.clear();
+                        self.notice = format!(
+                            "generated password, {:.0} bits of entropy",
+                            self.password_policy.entropy()
+                        );
+                    }
+                    Err(e) => self.error_message = e.to_string(),
+                }
+            }
+            KeyCode::Char('p')
+                if self.focus == Focus::Value
+                    && key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                let policy = PassphrasePolicy::default();
+                self.value
+                    .clone_from(&generator::generate_passphrase(&policy));
+                self.error_message.clear();
+                self.notice = format!(
+                    "generated passphrase, {:.0} bits of entropy",
+                    policy.entropy()
+                );
+            }
             KeyCode::Enter => {
                 if self.name.is_empty() {
                     self.error_message = "Name must not be empty".to_string();
@@ -168,7 +250,7 @@ impl SubkeyEditView {
                     return Some(EditResult::Confirm {
                         mode: self.mode.clone(),
                         name: self.name.trim().to_string(),
-                        value: self.value.trim().to_string(),
+                        value: Zeroizing::new(self.value.trim().to_string()),
                     });
                 }
             }
@@ -198,10 +280,12 @@ impl SubkeyEditView {
             KeyCode::Char(c) if self.focus == Focus::Value => {
                 self.value.push(c);
                 self.error_message.clear();
+                self.notice.clear();
             }
             KeyCode::Backspace if self.focus == Focus::Value => {
                 self.value.pop();
                 self.error_message.clear();
+                self.notice.clear();
             }
             _ => {}
         }